@@ -0,0 +1,335 @@
+//! Adaptive time stepping for the Spin-Langevin equation
+//!
+//! The difference between the stage one and stage two Magnus propagators,
+//! |\Omega_{22} - \Omega_{12}|, is used as a local error estimate of the deterministic part of
+//! the step. For the 4th order propagator, the difference between the final propagator and
+//! the propagator of the previous fixed point iteration is used instead. Steps are accepted or
+//! rejected against a tolerance and the step size is controlled with a PI controller.
+//!
+//! The Brownian path is sampled lazily and stored as a sequence of increments over
+//! consecutive time segments. When a step is rejected, the segments are refined by
+//! Brownian bridge subdivision, so that every retry sees the same noise path.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use ndarray::{Array2, ArrayView1, ArrayViewMut1, Zip};
+use num_traits::Zero;
+use rand::Rng;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::{par_rng_fn_rows, spin_langevin_step_noise, MagnusScheme, SpinIntegrator, SpinLangevinOpts};
+
+/// A Brownian increment over a time segment of length `dt`
+struct NoiseSegment{
+    dt: f64,
+    dw: Array2<Vector3d4xf64>
+}
+
+/// Lazily sampled Brownian path ahead of the current time of the integrator.
+/// The increments are scaled by \sqrt{b}
+struct BrownianPath<'a, R, Fr>
+where R: Rng + Send + Sync,
+      Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+{
+    segments: VecDeque<NoiseSegment>,
+    shape: (usize, usize),
+    b_sqrt: Aligned4xf64,
+    rng_arr: &'a Vec<Mutex<R>>,
+    rand_xi_f: &'a Fr
+}
+
+impl<'a, R, Fr> BrownianPath<'a, R, Fr>
+where R: Rng + Send + Sync,
+      Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+{
+    fn new(shape: (usize, usize), b: f64, rng_arr: &'a Vec<Mutex<R>>, rand_xi_f: &'a Fr) -> Self{
+        Self{segments: VecDeque::new(), shape, b_sqrt: Aligned4xf64::from(b.sqrt()), rng_arr, rand_xi_f}
+    }
+
+    /// Sample \sqrt{b} \xi for every element
+    fn sample(&self) -> Array2<Vector3d4xf64>{
+        let mut xi = Array2::from_elem(self.shape, Zero::zero());
+        par_rng_fn_rows(&mut xi, self.rng_arr, self.b_sqrt, self.rand_xi_f);
+        xi
+    }
+
+    /// Ensure that a segment boundary exists at time `tau` past the current time,
+    /// either by extending the path with fresh increments or by Brownian bridge subdivision
+    fn split_at(&mut self, tau: f64){
+        let eps = 1.0e-12 * tau;
+        let mut t_end = 0.0;
+        for k in 0..self.segments.len(){
+            let t_start = t_end;
+            t_end += self.segments[k].dt;
+            if (t_end - tau).abs() <= eps{
+                return;
+            }
+            if t_end > tau{
+                // Brownian bridge: W(s) = (s/h) W(h) + \sqrt{s (h - s) / h} \xi
+                let h = self.segments[k].dt;
+                let s = tau - t_start;
+                let w_s = Aligned4xf64::from(s / h);
+                let w_xi = Aligned4xf64::from((s * (h - s) / h).sqrt());
+                let mut dw_a = self.sample();
+                let seg = &mut self.segments[k];
+                Zip::from(&mut dw_a).and(&mut seg.dw)
+                    .par_apply(|a, b|{
+                        *a = *b * w_s + *a * w_xi;
+                        *b -= *a;
+                    });
+                seg.dt = h - s;
+                self.segments.insert(k, NoiseSegment{dt: s, dw: dw_a});
+                return;
+            }
+        }
+        // Extend the path with a fresh increment
+        let h = tau - t_end;
+        let mut dw = self.sample();
+        let w = Aligned4xf64::from(h.sqrt());
+        dw.par_map_inplace(|x| *x *= w);
+        self.segments.push_back(NoiseSegment{dt: h, dw});
+    }
+
+    /// Sum of the increments between the segment boundaries at `tau_a` and `tau_b`
+    fn increment(&self, tau_a: f64, tau_b: f64, dw: &mut Array2<Vector3d4xf64>){
+        let eps = 1.0e-12 * tau_b;
+        dw.fill(Zero::zero());
+        let mut t_end = 0.0;
+        for seg in self.segments.iter(){
+            let t_start = t_end;
+            t_end += seg.dt;
+            if t_start + eps >= tau_b{
+                break;
+            }
+            if t_end > tau_a + eps{
+                Zip::from(&mut *dw).and(&seg.dw).par_apply(|a, b| *a += *b);
+            }
+        }
+    }
+
+    /// Discard the increments up to `tau` and advance the current time to `tau`
+    fn advance(&mut self, tau: f64){
+        let eps = 1.0e-12 * tau;
+        let mut t_end = 0.0;
+        while let Some(seg) = self.segments.front(){
+            t_end += seg.dt;
+            if t_end > tau + eps{
+                break;
+            }
+            self.segments.pop_front();
+        }
+    }
+}
+
+/// Statistics of an adaptive integration
+#[derive(Copy, Clone, Debug)]
+pub struct IntegratorStats{
+    /// Time reached by the integration
+    pub t: f64,
+    pub accepted: usize,
+    pub rejected: usize,
+}
+
+/// Adaptive step size driver for the Spin-Langevin equation
+///
+/// The local error of a step is estimated as the average of |\Omega_{22} - \Omega_{12}| and
+/// normalized by `tol`. A step is accepted if the normalized error is at most one and the average
+/// stage one norm is below `opts.h_max`. The next step size is chosen by the PI controller
+///     dt <- dt * safety * err^{-0.7/k} * err_prev^{0.4/k}
+/// with k the order of `scheme`, clipped to the range [fac_min, fac_max] of the current step.
+pub struct SpinLangevinIntegrator<I: SpinIntegrator = MagnusScheme>{
+    pub opts: SpinLangevinOpts,
+    pub scheme: I,
+    /// Tolerance of the average local error of the generators
+    pub tol: f64,
+    /// Current step size. Updated on exit so that a subsequent integration resumes with it.
+    pub dt: f64,
+    pub dt_min: f64,
+    pub dt_max: f64,
+    pub safety: f64,
+    pub fac_min: f64,
+    pub fac_max: f64,
+    err_prev: f64,
+}
+
+impl SpinLangevinIntegrator{
+    /// Adaptive integrator with the 2nd order Magnus propagator
+    pub fn new(dt: f64, tol: f64) -> Self{
+        Self::with_scheme(MagnusScheme::default(), dt, tol)
+    }
+}
+
+impl<I: SpinIntegrator> SpinLangevinIntegrator<I>{
+    pub fn with_scheme(scheme: I, dt: f64, tol: f64) -> Self{
+        Self{
+            opts: SpinLangevinOpts::default(),
+            scheme,
+            tol, dt, dt_min: 1.0e-8 * dt, dt_max: f64::INFINITY,
+            safety: 0.9, fac_min: 0.2, fac_max: 5.0,
+            err_prev: 1.0
+        }
+    }
+
+    /// Integrate the Spin-Langevin equation from t0 to tf, overwriting `spins` with the state at tf.
+    /// The parameters are the same as for `spin_langevin_step`.
+    ///
+    /// Returns Err if the step size falls below `dt_min`. The statistics then record the time
+    /// reached, and `spins` holds the state at that time.
+    pub fn integrate<Fh, R, Fr>(
+        &mut self, t0: f64, tf: f64,
+        spins: &mut Array2<Vector3d4xf64>,
+        eta: f64, b: f64,
+        haml_fn: Fh,
+        rng_arr: & Vec<Mutex<R>>,
+        rand_xi_f: Fr,
+    ) -> Result<IntegratorStats, IntegratorStats>
+    where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!(b >= 0.0, "Stochastic strength must be non-negative");
+        assert!(tf >= t0, "SpinLangevinIntegrator: tf must not precede t0");
+        assert!(self.dt > 0.0, "SpinLangevinIntegrator: initial dt must be positive");
+        let num_threads = rayon::current_num_threads();
+        assert!(rng_arr.len() >= num_threads, "Insufficient number of RNGs for multithreading");
+
+        let sh = spins.shape();
+        let sh = (sh[0], sh[1]);
+        let k = self.scheme.order() as f64;
+        let (alpha, beta) = (0.7 / k, 0.4 / k);
+
+        let mut path = BrownianPath::new(sh, b, rng_arr, &rand_xi_f);
+        let mut spins_tf = spins.clone();
+        let mut chi1 = Array2::from_elem(sh, Zero::zero());
+        let mut chi2 = Array2::from_elem(sh, Zero::zero());
+        let mut stats = IntegratorStats{t: t0, accepted: 0, rejected: 0};
+        let mut last_rejected = false;
+
+        while stats.t < tf {
+            let mut dt = self.dt.min(self.dt_max);
+            let last_step = stats.t + dt >= tf;
+            if last_step {
+                dt = tf - stats.t;
+            }
+
+            if b > 0.0 {
+                path.split_at(dt / 2.0);
+                path.split_at(dt);
+                path.increment(0.0, dt / 2.0, &mut chi1);
+                path.increment(dt / 2.0, dt, &mut chi2);
+                let w = Aligned4xf64::from((2.0 / dt).sqrt());
+                chi1.par_map_inplace(|x| *x *= w);
+                chi2.par_map_inplace(|x| *x *= w);
+            }
+
+            let norms = spin_langevin_step_noise(&*spins, &mut spins_tf, stats.t, dt, eta, b,
+                                                 &haml_fn, &chi1, &chi2, &self.scheme);
+            let err = (norms.delta / self.tol).max(1.0e-10);
+
+            if err <= 1.0 && norms.omega12 < self.opts.h_max {
+                std::mem::swap(spins, &mut spins_tf);
+                if b > 0.0 {
+                    path.advance(dt);
+                }
+                stats.t = if last_step { tf } else { stats.t + dt };
+                stats.accepted += 1;
+
+                let fac_max = if last_rejected { 1.0 } else { self.fac_max };
+                let fac = self.safety * err.powf(-alpha) * self.err_prev.powf(beta);
+                // Do not let a truncated final step shrink the step size for the next integration
+                if !last_step || dt >= self.dt {
+                    self.dt = dt * fac.max(self.fac_min).min(fac_max);
+                }
+                self.err_prev = err;
+                last_rejected = false;
+            } else {
+                stats.rejected += 1;
+                let fac = if err > 1.0 {
+                    self.safety * err.powf(-1.0 / k)
+                } else {
+                    0.5
+                };
+                self.dt = dt * fac.max(self.fac_min).min(1.0);
+                last_rejected = true;
+                if self.dt < self.dt_min {
+                    return Err(stats);
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ndarray::Array2;
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+    use std::sync::Mutex;
+
+    use super::SpinLangevinIntegrator;
+
+    #[test]
+    fn test_adaptive_precession(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(1234);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let mut spins : Array2<Vector3d4xf64> = Array2::from_elem((2, 3), Zero::zero());
+        for m in spins.iter_mut(){
+            m[0] = Aligned4xf64::from(1.0);
+        }
+        // Uniform precession about z with angular frequency omega
+        let omega = 2.0;
+        let mut integrator = SpinLangevinIntegrator::new(0.01, 1.0e-6);
+        let stats = integrator.integrate(0.0, 1.0, &mut spins, 0.0, 0.0,
+            |_t, _m, h|{
+                for hi in h.iter_mut(){
+                    *hi = Zero::zero();
+                    hi[2] = Aligned4xf64::from(omega);
+                }
+            },
+            &rng_arr, |_r| Zero::zero()
+        ).expect("Adaptive integration failed");
+
+        assert!((stats.t - 1.0).abs() < 1.0e-12);
+        for m in spins.iter(){
+            assert!((m[0].dat[0] - omega.cos()).abs() < 1.0e-8);
+            assert!((m[1].dat[0] - omega.sin()).abs() < 1.0e-8);
+        }
+
+        // With noise, steps are rejected and retried on the same Brownian path
+        let mut integrator = SpinLangevinIntegrator::new(0.1, 1.0e-4);
+        let stats = integrator.integrate(0.0, 1.0, &mut spins, 0.1, 0.05,
+            |_t, m, h|{
+                for (hi, mi) in h.iter_mut().zip(m.iter()){
+                    *hi = Zero::zero();
+                    hi[2] = mi[0] * Aligned4xf64::from(omega);
+                }
+            },
+            &rng_arr, |r| Vector3d4xf64::from_fn(|_i, _j| {
+                let mut x = Aligned4xf64::from(0.0);
+                for xi in x.dat.iter_mut(){
+                    *xi = r.sample(rand_distr::StandardNormal);
+                }
+                x
+            })
+        ).expect("Adaptive integration failed");
+        assert!((stats.t - 1.0).abs() < 1.0e-12);
+        for m in spins.iter(){
+            let norm = (m[0]*m[0] + m[1]*m[1] + m[2]*m[2]).map(f64::sqrt);
+            for &x in norm.dat.iter(){
+                assert!((x - 1.0).abs() < 1.0e-10);
+            }
+        }
+    }
+}
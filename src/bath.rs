@@ -0,0 +1,505 @@
+//! Bath couplings of the Spin-Langevin equation
+//!
+//! `spin_langevin_step` takes a scalar damping \eta and noise strength b for each call. A `SpinBath`
+//! instead supplies the dissipative field and the scaling of the Brownian increments, so that both
+//! may depend on time within a step. The dissipative term is evaluated at every stage time at which
+//! the integration scheme evaluates the local fields, and the noise of a half step [t_a, t_b] is
+//! scaled by the mean strength over the half step,
+//!     \chi = \sqrt{ \bar{b} } \xi,          \bar{b} = (1 / (t_b - t_a)) \int_{t_a}^{t_b} b(t) dt
+//! which is the variance of  \int_{t_a}^{t_b} \sqrt{b(t)} dW(t) / \sqrt{t_b - t_a}.
+//!
+//! Time-dependent strengths are given as `Coefficient`s, which are either constants or closures of
+//! the time. A coupling that follows the anneal fraction is obtained by composing with the path
+//! of an `AnnealingHamiltonian`, e.g. `|t| eta0 * schedule.b(ham.s(t))`.
+//!
+//! Spins may also couple to the bath individually. `SpinwiseBath` has a damping \eta_i and a noise
+//! strength b_i for each spin, and `TensorBath` has a symmetric damping tensor \Gamma_i, with the
+//! dissipative field  -\Gamma_i (h_i \cross m_i)  and the noise field \chi_i with covariance B_i.
+//! The Boltzmann distribution at the temperature T is stationary when the fluctuation-dissipation
+//! relation  B_i = 2 k_B T \Gamma_i  holds, which the `thermal` constructors impose.
+//! The parameters are packed in chunks of four spins like the spins themselves, with inert
+//! padding lanes.
+//!
+//! The `BathCoupling` selects the axes through which the spins couple to the bath. In the
+//! dephasing model of qubit annealing the bath couples only through \sigma_z, so that the noise
+//! field is restricted to \hat{z} and the dissipative field is projected to
+//! -\eta \hat{z} \hat{z} \cdot (h \cross m), which is the tensor bath with \Gamma = \eta \hat{z} \hat{z}^T.
+//! Zeroing the x and y components in `rand_xi_f` alone would leave the isotropic dissipation
+//! unbalanced by the noise.
+
+use nalgebra::Matrix3;
+use ndarray::{ArrayView1, ArrayViewMut1, Array2, Axis};
+use ndarray::parallel::prelude::*;
+use simd_phys::r3::{Matrix3d4xf64, Vector3d4xf64};
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::{avg_field_row, num_chunks, pack_lanes, sl_add_dissipative, SpinIntegrator, SpinLangevinRowWorkpad};
+use crate::philox::{counter_noise_row, par_counter_rows, PhiloxRng};
+
+/// A scalar strength that may depend on time
+pub trait Coefficient : Sync{
+    fn at(&self, t: f64) -> f64;
+
+    /// Mean value over [t_a, t_b] by Simpson's rule
+    fn mean(&self, t_a: f64, t_b: f64) -> f64{
+        (self.at(t_a) + 4.0 * self.at((t_a + t_b) / 2.0) + self.at(t_b)) / 6.0
+    }
+}
+
+impl Coefficient for f64{
+    fn at(&self, _t: f64) -> f64{
+        *self
+    }
+
+    fn mean(&self, _t_a: f64, _t_b: f64) -> f64{
+        *self
+    }
+}
+
+impl<F> Coefficient for F where F: Fn(f64) -> f64 + Sync{
+    fn at(&self, t: f64) -> f64{
+        self(t)
+    }
+}
+
+/// The coupling of the spins to their environment
+pub trait SpinBath : Sync{
+    /// Add the dissipative field at time t to the row of local fields `h`, which holds the
+    /// Hamiltonian fields of the spins `m` on entry
+    fn add_dissipative(&self, t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: &ArrayView1<Vector3d4xf64>);
+
+    /// Scale the row of standard normal samples `chi` to the noise of the half step [t_a, t_b],
+    /// so that the Brownian increment over the half step is  \sqrt{(t_b - t_a)} \chi
+    fn scale_noise(&self, t_a: f64, t_b: f64, chi: &mut ArrayViewMut1<Vector3d4xf64>);
+
+    /// Isotropic average tr(B)/3 of the noise covariance over [t_a, t_b] and over the spins,
+    /// passed to the integration scheme as the noise strength of the step
+    fn mean_b(&self, t_a: f64, t_b: f64) -> f64;
+}
+
+/// Isotropic damping \eta and noise strength b, identical for all spins
+#[derive(Copy, Clone, Debug)]
+pub struct IsotropicBath<E=f64, B=f64>{
+    pub eta: E,
+    pub b: B
+}
+
+impl<E: Coefficient, B: Coefficient> IsotropicBath<E, B>{
+    pub fn new(eta: E, b: B) -> Self{
+        Self{eta, b}
+    }
+}
+
+impl<E: Coefficient, B: Coefficient> SpinBath for IsotropicBath<E, B>{
+    fn add_dissipative(&self, t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: &ArrayView1<Vector3d4xf64>){
+        sl_add_dissipative(h, m, self.eta.at(t));
+    }
+
+    fn scale_noise(&self, t_a: f64, t_b: f64, chi: &mut ArrayViewMut1<Vector3d4xf64>){
+        let b = self.b.mean(t_a, t_b);
+        assert!(b >= 0.0, "Stochastic strength must be non-negative");
+        let b_sqrt = Aligned4xf64::from(b.sqrt());
+        for c in chi.iter_mut(){
+            *c *= b_sqrt;
+        }
+    }
+
+    fn mean_b(&self, t_a: f64, t_b: f64) -> f64{
+        self.b.mean(t_a, t_b)
+    }
+}
+
+/// The axes through which the spins couple to the bath
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BathCoupling{
+    /// All three axes, as in `spin_langevin_step`
+    Isotropic,
+    /// Only the z axis (\sigma_z), the dephasing model
+    Dephasing
+}
+
+impl Default for BathCoupling{
+    fn default() -> Self{
+        BathCoupling::Isotropic
+    }
+}
+
+/// Damping \eta and noise strength b coupled only through the z axis of each spin
+#[derive(Copy, Clone, Debug)]
+pub struct DephasingBath<E=f64, B=f64>{
+    pub eta: E,
+    pub b: B
+}
+
+impl<E: Coefficient, B: Coefficient> DephasingBath<E, B>{
+    pub fn new(eta: E, b: B) -> Self{
+        Self{eta, b}
+    }
+}
+
+impl<E: Coefficient, B: Coefficient> SpinBath for DephasingBath<E, B>{
+    fn add_dissipative(&self, t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: &ArrayView1<Vector3d4xf64>){
+        let eta = Aligned4xf64::from(self.eta.at(t));
+        for (hc, mc) in h.iter_mut().zip(m.iter()){
+            // z component of h \cross m
+            let dh_z = hc[0] * mc[1] - hc[1] * mc[0];
+            hc[2] -= dh_z * eta;
+        }
+    }
+
+    fn scale_noise(&self, t_a: f64, t_b: f64, chi: &mut ArrayViewMut1<Vector3d4xf64>){
+        let b = self.b.mean(t_a, t_b);
+        assert!(b >= 0.0, "Stochastic strength must be non-negative");
+        let b_sqrt = Aligned4xf64::from(b.sqrt());
+        for c in chi.iter_mut(){
+            c[0] = Aligned4xf64::from(0.0);
+            c[1] = Aligned4xf64::from(0.0);
+            c[2] *= b_sqrt;
+        }
+    }
+
+    fn mean_b(&self, t_a: f64, t_b: f64) -> f64{
+        self.b.mean(t_a, t_b) / 3.0
+    }
+}
+
+/// Isotropic damping \eta_i and noise strength b_i for each spin
+#[derive(Clone, Debug)]
+pub struct SpinwiseBath{
+    eta: Vec<Aligned4xf64>,
+    b_sqrt: Vec<Aligned4xf64>,
+    b_mean: f64
+}
+
+impl SpinwiseBath{
+    pub fn new(eta: &[f64], b: &[f64]) -> Self{
+        assert_eq!(eta.len(), b.len(), "SpinwiseBath: mismatching number of spins");
+        assert!(b.iter().all(|&b| b >= 0.0), "Stochastic strength must be non-negative");
+        let b_sqrt : Vec<f64> = b.iter().map(|b| b.sqrt()).collect();
+        let b_mean = b.iter().sum::<f64>() / b.len().max(1) as f64;
+
+        Self{eta: pack_lanes(eta), b_sqrt: pack_lanes(&b_sqrt), b_mean}
+    }
+
+    /// Damping \eta_i at the temperature k_B T, with b_i = 2 \eta_i k_B T
+    pub fn thermal(eta: &[f64], kt: f64) -> Self{
+        assert!(kt >= 0.0, "SpinwiseBath: negative temperature");
+        let b : Vec<f64> = eta.iter().map(|eta| 2.0 * eta * kt).collect();
+        Self::new(eta, &b)
+    }
+
+    pub fn num_chunks(&self) -> usize{
+        self.eta.len()
+    }
+}
+
+impl SpinBath for SpinwiseBath{
+    fn add_dissipative(&self, _t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: &ArrayView1<Vector3d4xf64>){
+        assert_eq!(h.len(), self.num_chunks(), "SpinwiseBath: mismatching number of chunks");
+        for ((hc, mc), &eta) in h.iter_mut().zip(m.iter()).zip(self.eta.iter()){
+            let dh = hc.cross(mc);
+            *hc -= dh * eta;
+        }
+    }
+
+    fn scale_noise(&self, _t_a: f64, _t_b: f64, chi: &mut ArrayViewMut1<Vector3d4xf64>){
+        for (c, &b_sqrt) in chi.iter_mut().zip(self.b_sqrt.iter()){
+            *c *= b_sqrt;
+        }
+    }
+
+    fn mean_b(&self, _t_a: f64, _t_b: f64) -> f64{
+        self.b_mean
+    }
+}
+
+fn assert_sym_psd(a: &Matrix3<f64>, name: &str){
+    let scale = a.norm().max(1.0);
+    assert!((a - a.transpose()).norm() <= 1.0e-12 * scale, "TensorBath: {} is not symmetric", name);
+    assert!(a.symmetric_eigen().eigenvalues.iter().all(|&l| l >= -1.0e-12 * scale),
+            "TensorBath: {} is not positive semidefinite", name);
+}
+
+/// The symmetric square root of a symmetric positive semidefinite matrix
+fn sym_sqrt(a: &Matrix3<f64>) -> Matrix3<f64>{
+    let eig = a.symmetric_eigen();
+    let d = Matrix3::from_diagonal(&eig.eigenvalues.map(|l| l.max(0.0).sqrt()));
+    eig.eigenvectors * d * eig.eigenvectors.transpose()
+}
+
+/// Apply a packed 3x3 matrix to a chunk
+#[inline]
+fn mat_vec(a: &Matrix3d4xf64, v: &Vector3d4xf64) -> Vector3d4xf64{
+    let mut w = Vector3d4xf64::zeros();
+    for i in 0..3{
+        for j in 0..3{
+            w[i] += a[(i, j)] * v[j];
+        }
+    }
+    w
+}
+
+/// Damping tensor \Gamma_i and noise covariance B_i for each spin
+#[derive(Clone, Debug)]
+pub struct TensorBath{
+    gamma: Vec<Matrix3d4xf64>,
+    noise: Vec<Matrix3d4xf64>,
+    b_mean: f64
+}
+
+impl TensorBath{
+    /// Damping tensors `gamma` and noise covariances `b`, which must be symmetric and positive
+    /// semidefinite
+    pub fn new(gamma: &[Matrix3<f64>], b: &[Matrix3<f64>]) -> Self{
+        assert_eq!(gamma.len(), b.len(), "TensorBath: mismatching number of spins");
+        gamma.iter().for_each(|g| assert_sym_psd(g, "damping tensor"));
+        b.iter().for_each(|b| assert_sym_psd(b, "noise covariance"));
+        let noise : Vec<Matrix3<f64>> = b.iter().map(sym_sqrt).collect();
+        let b_mean = b.iter().map(|b| b.trace() / 3.0).sum::<f64>() / b.len().max(1) as f64;
+
+        Self{gamma: Self::pack(gamma), noise: Self::pack(&noise), b_mean}
+    }
+
+    /// Damping tensors `gamma` at the temperature k_B T, with B_i = 2 k_B T \Gamma_i
+    pub fn thermal(gamma: &[Matrix3<f64>], kt: f64) -> Self{
+        assert!(kt >= 0.0, "TensorBath: negative temperature");
+        let b : Vec<Matrix3<f64>> = gamma.iter().map(|g| g * (2.0 * kt)).collect();
+        Self::new(gamma, &b)
+    }
+
+    fn pack(mats: &[Matrix3<f64>]) -> Vec<Matrix3d4xf64>{
+        let mut packed = vec![Matrix3d4xf64::zeros(); num_chunks(mats.len())];
+        for i in 0..3{
+            for j in 0..3{
+                let entries : Vec<f64> = mats.iter().map(|m| m[(i, j)]).collect();
+                for (p, x) in packed.iter_mut().zip(pack_lanes(&entries)){
+                    p[(i, j)] = x;
+                }
+            }
+        }
+        packed
+    }
+
+    pub fn num_chunks(&self) -> usize{
+        self.gamma.len()
+    }
+}
+
+impl SpinBath for TensorBath{
+    fn add_dissipative(&self, _t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: &ArrayView1<Vector3d4xf64>){
+        assert_eq!(h.len(), self.num_chunks(), "TensorBath: mismatching number of chunks");
+        for ((hc, mc), g) in h.iter_mut().zip(m.iter()).zip(self.gamma.iter()){
+            let dh = hc.cross(mc);
+            *hc -= mat_vec(g, &dh);
+        }
+    }
+
+    fn scale_noise(&self, _t_a: f64, _t_b: f64, chi: &mut ArrayViewMut1<Vector3d4xf64>){
+        for (c, l) in chi.iter_mut().zip(self.noise.iter()){
+            *c = mat_vec(l, c);
+        }
+    }
+
+    fn mean_b(&self, _t_a: f64, _t_b: f64) -> f64{
+        self.b_mean
+    }
+}
+
+/// Peform a step of the Spin-Langevin equation as in `spin_langevin_step_counter`, with the
+/// damping and noise supplied by `bath`. The standard normal samples of `rand_xi_f` are drawn from
+/// `counter_rng(seed, step, row, chunk)` before they are scaled by the bath, so a given seed
+/// reproduces the trajectory for any number of threads.
+///
+/// Returns the average magnitude of the final propagator of each step.
+pub fn spin_langevin_step_bath<I, S, Fh, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    bath: &S,
+    haml_fn: Fh,
+    seed: u64, step: u32,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> f64
+    where I: SpinIntegrator,
+          S: SpinBath,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          Fr: Fn(&mut PhiloxRng) -> Vector3d4xf64 + Send + Sync
+{
+    assert_eq!(spins_tf.raw_dim(), spins_t0.raw_dim());
+    let h_shape = spins_tf.shape();
+    let h_shape = (h_shape[0], h_shape[1]);
+    let t1 = t0 + delta_t / 2.0;
+    let t2 = t0 + delta_t;
+    let b_mean = bath.mean_b(t0, t2);
+    // The scheme adds no dissipation of its own, so the bath is evaluated at its stage times
+    let bath_haml_fn = |t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+        haml_fn(t, m, h);
+        bath.add_dissipative(t, h, m);
+    };
+
+    let avg_om = par_counter_rows(
+        spins_t0.axis_iter(Axis(0)).into_par_iter().zip(spins_tf.axis_iter_mut(Axis(0)).into_par_iter()),
+        || SpinLangevinRowWorkpad::from_shape(h_shape.1),
+        |row, work: &mut SpinLangevinRowWorkpad, (m0, mf)|{
+            counter_noise_row(work, seed, step, row, Aligned4xf64::from(1.0), &rand_xi_f);
+            bath.scale_noise(t0, t1, &mut work.chi1.view_mut());
+            bath.scale_noise(t1, t2, &mut work.chi2.view_mut());
+            work.b = b_mean;
+            scheme.step_row(t0, delta_t, 0.0, &bath_haml_fn, m0, mf, work);
+
+            avg_field_row(&work.omega2.view())
+        });
+
+    avg_om / h_shape.0 as f64
+}
+
+#[cfg(test)]
+mod tests{
+    use nalgebra::Matrix3;
+    use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1};
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_distr::StandardNormal;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{spin_langevin_step_bath, DephasingBath, IsotropicBath, SpinBath, SpinwiseBath, TensorBath};
+    use crate::philox::spin_langevin_step_counter;
+    use crate::MagnusScheme;
+
+    fn rand_xi_f<R: Rng>(rng: &mut R) -> Vector3d4xf64{
+        let mut v = Vector3d4xf64::zeros();
+        for a in 0..3{
+            for x in v[a].dat.iter_mut(){
+                *x = rng.sample(StandardNormal);
+            }
+        }
+        v
+    }
+
+    fn haml_fn(_t: f64, _m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        for hi in h.iter_mut(){
+            hi[0] = Aligned4xf64::from(0.0);
+            hi[1] = Aligned4xf64::from(0.0);
+            hi[2] = Aligned4xf64::from(1.0);
+        }
+    }
+
+    #[test]
+    fn test_time_dependent_bath(){
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((1, 2), Zero::zero());
+        m0.iter_mut().for_each(|m| m[0] = Aligned4xf64::from(1.0));
+        let scheme = MagnusScheme::Magnus2;
+
+        // Constant strengths reproduce the scalar step
+        let (mut mf1, mut mf2) = (m0.clone(), m0.clone());
+        spin_langevin_step_counter(&m0, &mut mf1, 0.0, 0.05, 0.1, 0.3, haml_fn, 5, 0, rand_xi_f, &scheme);
+        spin_langevin_step_bath(&m0, &mut mf2, 0.0, 0.05, &IsotropicBath::new(0.1, 0.3),
+                                haml_fn, 5, 0, rand_xi_f, &scheme);
+        for (a, b) in mf1.iter().zip(mf2.iter()){
+            for c in 0..3{
+                assert_eq!(a[c].dat, b[c].dat);
+            }
+        }
+
+        // Relaxation with \eta(t) = c t:  \tan(\theta / 2) = \exp(-c t^2 / 2)
+        let c = 2.0;
+        let bath = IsotropicBath::new(|t: f64| c * t, 0.0);
+        let dt = 0.01;
+        let mut m = m0.clone();
+        let mut mf = m0.clone();
+        for k in 0..100{
+            spin_langevin_step_bath(&m, &mut mf, k as f64 * dt, dt, &bath, haml_fn, 6, k, rand_xi_f, &scheme);
+            std::mem::swap(&mut m, &mut mf);
+            if (k + 1) % 25 == 0{
+                let t = (k + 1) as f64 * dt;
+                let mz = m[(0, 1)][2].dat[3];
+                let theta = 2.0 * (-c * t * t / 2.0).exp().atan();
+                assert!((mz - theta.cos()).abs() < 1.0e-4, "t = {}: {} != {}", t, mz, theta.cos());
+            }
+        }
+    }
+
+    #[test]
+    fn test_spinwise_tensor_bath(){
+        let scheme = MagnusScheme::Magnus2;
+        let eta = [0.5, 1.0, 2.0, 0.0, 1.5];
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((3, 2), Zero::zero());
+        m0.iter_mut().for_each(|m| m[0] = Aligned4xf64::from(1.0));
+
+        // Each spin relaxes at its own rate:  \tan(\theta_i / 2) = \exp(-\eta_i t)
+        let bath = SpinwiseBath::new(&eta, &[0.0; 5]);
+        let (mut m, mut mf) = (m0.clone(), m0.clone());
+        for k in 0..100{
+            spin_langevin_step_bath(&m, &mut mf, k as f64 * 0.01, 0.01, &bath, haml_fn, 7, k, rand_xi_f, &scheme);
+            std::mem::swap(&mut m, &mut mf);
+        }
+        for (i, &eta_i) in eta.iter().enumerate(){
+            let mz = m[(2, i / 4)][2].dat[i % 4];
+            assert!((mz - (2.0 * (-eta_i).exp().atan()).cos()).abs() < 1.0e-4);
+        }
+
+        // Isotropic tensors reproduce the per-spin bath
+        let gamma : Vec<Matrix3<f64>> = eta.iter().map(|&e| Matrix3::identity() * e).collect();
+        let (mut mf1, mut mf2) = (m0.clone(), m0.clone());
+        spin_langevin_step_bath(&m0, &mut mf1, 0.0, 0.05, &SpinwiseBath::thermal(&eta, 0.2),
+                                haml_fn, 8, 0, rand_xi_f, &scheme);
+        spin_langevin_step_bath(&m0, &mut mf2, 0.0, 0.05, &TensorBath::thermal(&gamma, 0.2),
+                                haml_fn, 8, 0, rand_xi_f, &scheme);
+        for (a, b) in mf1.iter().zip(mf2.iter()){
+            assert!((a - b).iter().all(|x| x.dat.iter().all(|x| x.abs() < 1.0e-12)));
+        }
+
+        // The noise covariance satisfies B = 2 k_B T \Gamma
+        let gamma = Matrix3::new(2.0, 0.5, 0.0,
+                                 0.5, 1.0, 0.0,
+                                 0.0, 0.0, 0.0);
+        let bath = TensorBath::thermal(&[gamma], 0.5);
+        let mut rng = Xoshiro256Plus::seed_from_u64(9);
+        let mut cov = Matrix3::<f64>::zeros();
+        let n = 20000;
+        for _ in 0..n{
+            let mut chi = Array1::from_elem(1, rand_xi_f(&mut rng));
+            bath.scale_noise(0.0, 0.1, &mut chi.view_mut());
+            let c = chi[0].map(|x| x.dat[0]);
+            cov += c * c.transpose() / n as f64;
+            assert!(chi[0].iter().all(|x| x.dat[1..].iter().all(|&y| y == 0.0)));
+        }
+        assert!((cov - gamma).norm() < 0.05, "{}", cov);
+    }
+
+    #[test]
+    fn test_dephasing_bath(){
+        let scheme = MagnusScheme::Magnus2;
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((4, 2), Zero::zero());
+        m0.iter_mut().for_each(|m|{
+            m[0] = Aligned4xf64::from(0.6);
+            m[2] = Aligned4xf64::from(0.8);
+        });
+
+        // Same step as the tensor bath with \Gamma = \eta \hat{z} \hat{z}^T
+        let (eta, b) = (0.3, 0.4);
+        let zz = Matrix3::new(0.0, 0.0, 0.0,
+                              0.0, 0.0, 0.0,
+                              0.0, 0.0, 1.0);
+        let (mut mf1, mut mf2) = (m0.clone(), m0.clone());
+        spin_langevin_step_bath(&m0, &mut mf1, 0.0, 0.05, &DephasingBath::new(eta, b),
+                                haml_fn, 10, 0, rand_xi_f, &scheme);
+        spin_langevin_step_bath(&m0, &mut mf2, 0.0, 0.05, &TensorBath::new(&[zz * eta; 8], &[zz * b; 8]),
+                                haml_fn, 10, 0, rand_xi_f, &scheme);
+        for (a, b) in mf1.iter().zip(mf2.iter()){
+            assert!((a - b).iter().all(|x| x.dat.iter().all(|x| x.abs() < 1.0e-12)));
+        }
+
+        // In a longitudinal field, pure dephasing conserves m_z
+        let (mut m, mut mf) = (m0.clone(), m0.clone());
+        for k in 0..50{
+            spin_langevin_step_bath(&m, &mut mf, k as f64 * 0.05, 0.05, &DephasingBath::new(eta, b),
+                                    haml_fn, 11, k, rand_xi_f, &scheme);
+            std::mem::swap(&mut m, &mut mf);
+        }
+        assert!(m.iter().all(|mi| mi[2].dat.iter().all(|&mz| (mz - 0.8).abs() < 1.0e-10)));
+        assert!(m.iter().any(|mi| (mi[0].dat[0] - 0.6).abs() > 0.01));
+    }
+}
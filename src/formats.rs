@@ -0,0 +1,550 @@
+//! Reading and writing problem files
+//!
+//! Three formats are supported, all of which are converted to an `IsingProblem`:
+//!
+//!  - QUBO files in the qbsolv format: comment lines beginning with 'c', a program line
+//!    `p qubo <topology> <maxNodes> <nNodes> <nCouplers>`, then the nNodes diagonal entries `i i Q_ii`
+//!    and the nCouplers entries `i j Q_ij`, for the energy
+//!    E(x) = \sum_i Q_ii x_i + \sum_{i<j} Q_ij x_i x_j  of  x_i \in {0, 1}.
+//!  - Edge lists of whitespace separated lines `i j J_ij`, optionally prefixed with 'e' as in DIMACS,
+//!    where `i i h_i` is a local field. Lines that are empty or begin with '#' are skipped,
+//!    and an optional header `p <name> <nodes> <edges>` fixes the number of entries.
+//!  - Binary quadratic model JSON, either as the serializable form of dimod (schema 3.0.0 without
+//!    bytes) or as an object with "vartype", "linear" ({label: bias} or [[label, bias], ...]),
+//!    "quadratic" ([[u, v, bias], ...]) and "offset".
+//!
+//! Binary models are converted with x_i = (1 + s_i) / 2, which adds a constant to the offset.
+//! The variable labels of a file are relabeled to the contiguous indices 0..n, in numerical order
+//! if all labels are integers and in lexicographic order otherwise. Repeated entries of the same
+//! pair are summed. The writers use the indices of the problem as labels, and only the JSON
+//! format stores the offset.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::ising::IsingProblem;
+
+#[derive(Debug)]
+pub enum FormatError{
+    Io(io::Error),
+    /// A malformed or invalid line of a text file, numbered from 1
+    Parse{line: usize, msg: String},
+    /// An invalid problem, such as a malformed binary quadratic model
+    Invalid(String)
+}
+
+impl fmt::Display for FormatError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            FormatError::Io(e) => write!(f, "problem file I/O error: {}", e),
+            FormatError::Parse{line, msg} => write!(f, "problem file line {}: {}", line, msg),
+            FormatError::Invalid(msg) => write!(f, "invalid problem: {}", msg)
+        }
+    }
+}
+
+impl std::error::Error for FormatError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            FormatError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for FormatError{
+    fn from(e: io::Error) -> Self{
+        FormatError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for FormatError{
+    fn from(e: serde_json::Error) -> Self{
+        if e.is_io(){
+            FormatError::Io(e.into())
+        } else {
+            FormatError::Parse{line: e.line(), msg: format!("{}", e)}
+        }
+    }
+}
+
+/// A problem read from a file, with the original label of each spin
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledProblem{
+    pub problem: IsingProblem,
+    pub labels: Vec<String>
+}
+
+impl From<IsingProblem> for LabeledProblem{
+    /// Label each spin by its index
+    fn from(problem: IsingProblem) -> Self{
+        let labels = (0..problem.num_spins()).map(|i| i.to_string()).collect();
+        Self{problem, labels}
+    }
+}
+
+/// A quadratic unconstrained binary optimization problem
+///     E(x) = \sum_i q_i x_i + \sum_{(i, j)} Q_{ij} x_i x_j + offset,      x_i \in {0, 1}
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Qubo{
+    pub linear: Vec<f64>,
+    pub quadratic: Vec<(usize, usize, f64)>,
+    pub offset: f64
+}
+
+impl Qubo{
+    /// The equivalent Ising problem with x_i = (1 + s_i) / 2
+    pub fn to_ising(&self) -> IsingProblem{
+        let mut h : Vec<f64> = self.linear.iter().map(|&q| 0.5 * q).collect();
+        let mut offset = self.offset + 0.5 * self.linear.iter().sum::<f64>();
+        let couplings = self.quadratic.iter().map(|&(i, j, q)|{
+            h[i] += 0.25 * q;
+            h[j] += 0.25 * q;
+            offset += 0.25 * q;
+            (i, j, 0.25 * q)
+        }).collect();
+        let mut problem = IsingProblem::new(h, couplings);
+        problem.offset = offset;
+        problem
+    }
+
+    /// The equivalent QUBO of an Ising problem with s_i = 2 x_i - 1
+    pub fn from_ising(problem: &IsingProblem) -> Self{
+        let mut linear : Vec<f64> = problem.h.iter().map(|&h| 2.0 * h).collect();
+        let mut offset = problem.offset - problem.h.iter().sum::<f64>();
+        let quadratic = problem.couplings.iter().map(|&(i, j, jij)|{
+            linear[i] -= 2.0 * jij;
+            linear[j] -= 2.0 * jij;
+            offset += jij;
+            (i, j, 4.0 * jij)
+        }).collect();
+        Self{linear, quadratic, offset}
+    }
+}
+
+/// Collects the terms of a model with arbitrary labels
+#[derive(Default)]
+struct ModelBuilder{
+    index: HashMap<String, usize>,
+    labels: Vec<String>,
+    linear: Vec<(usize, f64)>,
+    quadratic: Vec<(usize, usize, f64)>
+}
+
+impl ModelBuilder{
+    fn var(&mut self, label: &str) -> usize{
+        if let Some(&i) = self.index.get(label){
+            return i;
+        }
+        let i = self.labels.len();
+        self.index.insert(label.to_string(), i);
+        self.labels.push(label.to_string());
+        i
+    }
+
+    fn add_linear(&mut self, u: &str, bias: f64){
+        let i = self.var(u);
+        self.linear.push((i, bias));
+    }
+
+    fn add_quadratic(&mut self, u: &str, v: &str, bias: f64){
+        let (i, j) = (self.var(u), self.var(v));
+        self.quadratic.push((i, j, bias));
+    }
+
+    /// Relabel to contiguous indices and convert to an Ising problem
+    fn finish(self, binary: bool, offset: f64) -> LabeledProblem{
+        let n = self.labels.len();
+        let mut order : Vec<usize> = (0..n).collect();
+        let ints : Option<Vec<i64>> = self.labels.iter().map(|l| l.parse::<i64>().ok()).collect();
+        match ints{
+            Some(v) => order.sort_by_key(|&i| v[i]),
+            None => order.sort_by(|&i, &j| self.labels[i].cmp(&self.labels[j]))
+        }
+        let mut new_index = vec![0; n];
+        for (k, &i) in order.iter().enumerate(){
+            new_index[i] = k;
+        }
+
+        let mut linear = vec![0.0; n];
+        for &(i, bias) in self.linear.iter(){
+            linear[new_index[i]] += bias;
+        }
+        let mut pairs = BTreeMap::new();
+        for &(i, j, bias) in self.quadratic.iter(){
+            let (a, b) = (new_index[i], new_index[j]);
+            *pairs.entry((a.min(b), a.max(b))).or_insert(0.0) += bias;
+        }
+        let quadratic : Vec<(usize, usize, f64)> = pairs.into_iter().map(|((i, j), bias)| (i, j, bias)).collect();
+
+        let problem = if binary{
+            Qubo{linear, quadratic, offset}.to_ising()
+        } else {
+            let mut problem = IsingProblem::new(linear, quadratic);
+            problem.offset = offset;
+            problem
+        };
+        let labels = order.into_iter().map(|i| self.labels[i].clone()).collect();
+
+        LabeledProblem{problem, labels}
+    }
+}
+
+fn parse_err<T>(line: usize, msg: String) -> Result<T, FormatError>{
+    Err(FormatError::Parse{line, msg})
+}
+
+fn parse_bias(line: usize, s: &str) -> Result<f64, FormatError>{
+    match s.parse::<f64>(){
+        Ok(x) if x.is_finite() => Ok(x),
+        Ok(_) => parse_err(line, format!("non-finite value {}", s)),
+        Err(e) => parse_err(line, format!("invalid value {}: {}", s, e))
+    }
+}
+
+fn parse_count(line: usize, s: &str) -> Result<usize, FormatError>{
+    s.parse::<usize>().or_else(|e| parse_err(line, format!("invalid count {}: {}", s, e)))
+}
+
+/// Read a QUBO file in the qbsolv format
+pub fn read_qubo<Rd: BufRead>(reader: Rd) -> Result<LabeledProblem, FormatError>{
+    let mut model = ModelBuilder::default();
+    // Line of the program line, maxNodes, nNodes, nCouplers
+    let mut program : Option<(usize, usize, usize, usize)> = None;
+    let (mut num_nodes, mut num_couplers) = (0, 0);
+    for (k, line) in reader.lines().enumerate(){
+        let line = line?;
+        let lineno = k + 1;
+        let cols : Vec<&str> = line.split_whitespace().collect();
+        if cols.is_empty() || cols[0] == "c"{
+            continue;
+        }
+        if cols[0] == "p"{
+            if program.is_some(){
+                return parse_err(lineno, "repeated program line".to_string());
+            }
+            if cols.len() != 6 || cols[1] != "qubo"{
+                return parse_err(lineno, "expected p qubo <topology> <maxNodes> <nNodes> <nCouplers>".to_string());
+            }
+            program = Some((lineno, parse_count(lineno, cols[3])?, parse_count(lineno, cols[4])?,
+                            parse_count(lineno, cols[5])?));
+            continue;
+        }
+        let max_nodes = match program{
+            Some((_, max_nodes, _, _)) => max_nodes,
+            None => return parse_err(lineno, "entry before the program line".to_string())
+        };
+        if cols.len() != 3{
+            return parse_err(lineno, format!("expected 3 columns, found {}", cols.len()));
+        }
+        let (i, j) = (parse_count(lineno, cols[0])?, parse_count(lineno, cols[1])?);
+        if i >= max_nodes || j >= max_nodes{
+            return parse_err(lineno, format!("node index exceeds maxNodes = {}", max_nodes));
+        }
+        let q = parse_bias(lineno, cols[2])?;
+        if i == j{
+            model.add_linear(cols[0], q);
+            num_nodes += 1;
+        } else {
+            model.add_quadratic(cols[0], cols[1], q);
+            num_couplers += 1;
+        }
+    }
+    match program{
+        None => Err(FormatError::Invalid("missing QUBO program line".to_string())),
+        Some((lineno, _, n_nodes, n_couplers)) if (n_nodes, n_couplers) != (num_nodes, num_couplers) =>
+            parse_err(lineno, format!("declared {} nodes and {} couplers, found {} and {}",
+                                      n_nodes, n_couplers, num_nodes, num_couplers)),
+        Some(_) => Ok(model.finish(true, 0.0))
+    }
+}
+
+/// Load a QUBO file in the qbsolv format
+pub fn load_qubo<P: AsRef<Path>>(path: P) -> Result<LabeledProblem, FormatError>{
+    read_qubo(BufReader::new(File::open(path)?))
+}
+
+/// Write a problem as a QUBO file in the qbsolv format. The constant of the conversion and the
+/// offset are written as a comment only.
+pub fn write_qubo<W: Write>(mut writer: W, problem: &IsingProblem) -> io::Result<()>{
+    let qubo = Qubo::from_ising(problem);
+    let n = qubo.linear.len();
+    writeln!(writer, "c offset {}", qubo.offset)?;
+    writeln!(writer, "p qubo 0 {} {} {}", n, n, qubo.quadratic.len())?;
+    for (i, q) in qubo.linear.iter().enumerate(){
+        writeln!(writer, "{} {} {}", i, i, q)?;
+    }
+    for &(i, j, q) in qubo.quadratic.iter(){
+        writeln!(writer, "{} {} {}", i.min(j), i.max(j), q)?;
+    }
+    Ok(())
+}
+
+/// Read an Ising problem from an edge list
+pub fn read_edge_list<Rd: BufRead>(reader: Rd) -> Result<LabeledProblem, FormatError>{
+    let mut model = ModelBuilder::default();
+    // Line of the header, number of nodes and edges
+    let mut header : Option<(usize, usize, usize)> = None;
+    let mut num_edges = 0;
+    for (k, line) in reader.lines().enumerate(){
+        let line = line?;
+        let lineno = k + 1;
+        let mut cols : Vec<&str> = line.split_whitespace().collect();
+        if cols.is_empty() || cols[0].starts_with('#'){
+            continue;
+        }
+        if cols[0] == "p"{
+            if header.is_some() || num_edges > 0 || !model.labels.is_empty(){
+                return parse_err(lineno, "the header must precede all entries".to_string());
+            }
+            if cols.len() != 4{
+                return parse_err(lineno, "expected p <name> <nodes> <edges>".to_string());
+            }
+            header = Some((lineno, parse_count(lineno, cols[2])?, parse_count(lineno, cols[3])?));
+            continue;
+        }
+        if cols[0] == "e"{
+            cols.remove(0);
+        }
+        if cols.len() != 3{
+            return parse_err(lineno, format!("expected 3 columns, found {}", cols.len()));
+        }
+        let bias = parse_bias(lineno, cols[2])?;
+        if cols[0] == cols[1]{
+            model.add_linear(cols[0], bias);
+        } else {
+            model.add_quadratic(cols[0], cols[1], bias);
+            num_edges += 1;
+        }
+        if let Some((_, nodes, _)) = header{
+            if model.labels.len() > nodes{
+                return parse_err(lineno, format!("more than the {} declared nodes", nodes));
+            }
+        }
+    }
+    if let Some((lineno, _, edges)) = header{
+        if edges != num_edges{
+            return parse_err(lineno, format!("declared {} edges, found {}", edges, num_edges));
+        }
+    }
+
+    Ok(model.finish(false, 0.0))
+}
+
+/// Load an Ising problem from an edge list file
+pub fn load_edge_list<P: AsRef<Path>>(path: P) -> Result<LabeledProblem, FormatError>{
+    read_edge_list(BufReader::new(File::open(path)?))
+}
+
+/// Write an Ising problem as an edge list, with the nonzero local fields as `i i h_i`.
+/// The offset is written as a comment only, and spins without any terms are not stored.
+pub fn write_edge_list<W: Write>(mut writer: W, problem: &IsingProblem) -> io::Result<()>{
+    writeln!(writer, "# offset {}", problem.offset)?;
+    writeln!(writer, "p ising {} {}", problem.num_spins(), problem.couplings.len())?;
+    for (i, &h) in problem.h.iter().enumerate().filter(|(_, &h)| h != 0.0){
+        writeln!(writer, "{} {} {}", i, i, h)?;
+    }
+    for &(i, j, jij) in problem.couplings.iter(){
+        writeln!(writer, "{} {} {}", i, j, jij)?;
+    }
+    Ok(())
+}
+
+fn json_label(v: &Value) -> Result<String, FormatError>{
+    match v{
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(x) => Ok(x.to_string()),
+        _ => Err(FormatError::Invalid(format!("unsupported variable label {}", v)))
+    }
+}
+
+fn json_f64(v: &Value, what: &str) -> Result<f64, FormatError>{
+    v.as_f64().filter(|x| x.is_finite())
+        .ok_or_else(|| FormatError::Invalid(format!("invalid {} {}", what, v)))
+}
+
+fn json_array<'a>(v: &'a Value, what: &str) -> Result<&'a Vec<Value>, FormatError>{
+    v.as_array().ok_or_else(|| FormatError::Invalid(format!("{} must be an array", what)))
+}
+
+/// Read a binary quadratic model from JSON
+pub fn read_bqm_json<Rd: Read>(reader: Rd) -> Result<LabeledProblem, FormatError>{
+    let bqm : Value = serde_json::from_reader(reader)?;
+    let obj = bqm.as_object().ok_or_else(|| FormatError::Invalid("expected a JSON object".to_string()))?;
+    let vartype = obj.get("variable_type").or_else(|| obj.get("vartype")).and_then(|v| v.as_str());
+    let binary = match vartype{
+        Some("SPIN") => false,
+        Some("BINARY") => true,
+        _ => return Err(FormatError::Invalid("the vartype must be SPIN or BINARY".to_string()))
+    };
+    let offset = match obj.get("offset"){
+        Some(v) => json_f64(v, "offset")?,
+        None => 0.0
+    };
+
+    let mut model = ModelBuilder::default();
+    if let Some(biases) = obj.get("linear_biases"){
+        // Serializable form of dimod
+        if obj.get("use_bytes").and_then(|v| v.as_bool()) == Some(true){
+            return Err(FormatError::Invalid("byte encoded models are not supported".to_string()));
+        }
+        let labels = obj.get("variable_labels").ok_or_else(|| FormatError::Invalid("missing variable_labels".to_string()))?;
+        let labels : Vec<String> = json_array(labels, "variable_labels")?.iter().map(json_label).collect::<Result<_, _>>()?;
+        let biases = json_array(biases, "linear_biases")?;
+        if biases.len() != labels.len(){
+            return Err(FormatError::Invalid("mismatching lengths of variable_labels and linear_biases".to_string()));
+        }
+        for (u, b) in labels.iter().zip(biases.iter()){
+            model.add_linear(u, json_f64(b, "linear bias")?);
+        }
+        let column = |key: &str| -> Result<&Vec<Value>, FormatError>{
+            json_array(obj.get(key).ok_or_else(|| FormatError::Invalid(format!("missing {}", key)))?, key)
+        };
+        let (heads, tails, qbiases) = (column("quadratic_head")?, column("quadratic_tail")?, column("quadratic_biases")?);
+        if heads.len() != tails.len() || heads.len() != qbiases.len(){
+            return Err(FormatError::Invalid("mismatching lengths of the quadratic arrays".to_string()));
+        }
+        for ((u, v), b) in heads.iter().zip(tails.iter()).zip(qbiases.iter()){
+            let (i, j) = match (u.as_u64(), v.as_u64()){
+                (Some(i), Some(j)) if (i as usize) < labels.len() && (j as usize) < labels.len() && i != j
+                    => (i as usize, j as usize),
+                _ => return Err(FormatError::Invalid(format!("invalid interaction ({}, {})", u, v)))
+            };
+            model.add_quadratic(&labels[i], &labels[j], json_f64(b, "quadratic bias")?);
+        }
+    } else {
+        match obj.get("linear"){
+            Some(Value::Object(m)) => for (u, b) in m.iter(){
+                model.add_linear(u, json_f64(b, "linear bias")?);
+            },
+            Some(Value::Array(a)) => for entry in a.iter(){
+                match entry.as_array().map(|e| e.as_slice()){
+                    Some([u, b]) => model.add_linear(&json_label(u)?, json_f64(b, "linear bias")?),
+                    _ => return Err(FormatError::Invalid(format!("invalid linear entry {}", entry)))
+                }
+            },
+            None => {},
+            Some(_) => return Err(FormatError::Invalid("linear must be an object or an array".to_string()))
+        }
+        if let Some(q) = obj.get("quadratic"){
+            for entry in json_array(q, "quadratic")?.iter(){
+                match entry.as_array().map(|e| e.as_slice()){
+                    Some([u, v, b]) if u != v =>
+                        model.add_quadratic(&json_label(u)?, &json_label(v)?, json_f64(b, "quadratic bias")?),
+                    _ => return Err(FormatError::Invalid(format!("invalid quadratic entry {}", entry)))
+                }
+            }
+        }
+    }
+
+    Ok(model.finish(binary, offset))
+}
+
+/// Load a binary quadratic model from a JSON file
+pub fn load_bqm_json<P: AsRef<Path>>(path: P) -> Result<LabeledProblem, FormatError>{
+    read_bqm_json(BufReader::new(File::open(path)?))
+}
+
+/// Write an Ising problem as a SPIN binary quadratic model in the serializable form of dimod.
+/// Integer labels are written as numbers.
+pub fn write_bqm_json<W: Write>(writer: W, problem: &LabeledProblem) -> Result<(), FormatError>{
+    let p = &problem.problem;
+    assert_eq!(problem.labels.len(), p.num_spins(), "write_bqm_json: mismatching number of labels");
+    let labels : Vec<Value> = problem.labels.iter().map(|l| match l.parse::<i64>(){
+        Ok(i) => json!(i),
+        Err(_) => json!(l)
+    }).collect();
+    let bqm = json!({
+        "basetype": "BinaryQuadraticModel",
+        "type": "BinaryQuadraticModel",
+        "version": {"bqm_schema": "3.0.0"},
+        "use_bytes": false,
+        "index_type": "int64",
+        "bias_type": "float64",
+        "num_variables": p.num_spins(),
+        "num_interactions": p.couplings.len(),
+        "variable_labels": labels,
+        "variable_type": "SPIN",
+        "offset": p.offset,
+        "info": {},
+        "linear_biases": p.h,
+        "quadratic_head": p.couplings.iter().map(|c| c.0).collect::<Vec<_>>(),
+        "quadratic_tail": p.couplings.iter().map(|c| c.1).collect::<Vec<_>>(),
+        "quadratic_biases": p.couplings.iter().map(|c| c.2).collect::<Vec<_>>()
+    });
+    serde_json::to_writer(writer, &bqm)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::{read_bqm_json, read_edge_list, read_qubo, write_bqm_json, write_edge_list, write_qubo,
+                FormatError, LabeledProblem, Qubo};
+    use crate::ising::IsingProblem;
+
+    fn configs(n: usize) -> impl Iterator<Item=Vec<i8>>{
+        (0..1u32 << n).map(move |c| (0..n).map(|i| if (c >> i) & 1 == 1 { 1 } else { -1 }).collect())
+    }
+
+    #[test]
+    fn test_problem_formats(){
+        let qubo_file = "c example\np qubo 0 10 3 3\n2 2 1.0\n7 7 -2.5\n\n4 4 0.5\n2 7 3.0\n7 4 -1.0\n2 4 0.25\n";
+        let lp = read_qubo(qubo_file.as_bytes()).unwrap();
+        assert_eq!(lp.labels, vec!["2", "4", "7"]);
+        // The Ising energies equal the QUBO energies
+        let (q, pairs) = ([1.0, 0.5, -2.5], [(0, 2, 3.0), (2, 1, -1.0), (0, 1, 0.25)]);
+        for s in configs(3){
+            let x : Vec<f64> = s.iter().map(|&si| if si > 0 { 1.0 } else { 0.0 }).collect();
+            let e : f64 = (0..3).map(|i| q[i] * x[i]).sum::<f64>()
+                + pairs.iter().map(|&(i, j, qij)| qij * x[i] * x[j]).sum::<f64>();
+            assert!((lp.problem.energy(&s) - e).abs() < 1.0e-12);
+        }
+        let back = Qubo::from_ising(&lp.problem).to_ising();
+        assert_eq!(back, lp.problem);
+
+        // Writing and reading preserves the energies up to the offset
+        let mut buf = Vec::new();
+        write_qubo(&mut buf, &lp.problem).unwrap();
+        let reread = read_qubo(buf.as_slice()).unwrap().problem;
+        let shift = reread.energy(&[1, 1, 1]) - lp.problem.energy(&[1, 1, 1]);
+        assert!(configs(3).all(|s| (reread.energy(&s) - lp.problem.energy(&s) - shift).abs() < 1.0e-12));
+        let mut buf = Vec::new();
+        write_edge_list(&mut buf, &lp.problem).unwrap();
+        let reread = read_edge_list(buf.as_slice()).unwrap().problem;
+        assert_eq!((reread.h.clone(), reread.couplings.clone()), (lp.problem.h.clone(), lp.problem.couplings.clone()));
+        let mut buf = Vec::new();
+        write_bqm_json(&mut buf, &lp).unwrap();
+        assert_eq!(read_bqm_json(buf.as_slice()).unwrap(), lp);
+
+        // Edge lists with repeated pairs, DIMACS prefixes and string labels
+        let edges = "# ring\np ising 3 4\ne a b -1\nb c 1.5\nc a 1\ne b a 0.5\nc c 0.2\n";
+        let lp = read_edge_list(edges.as_bytes()).unwrap();
+        assert_eq!(lp.labels, vec!["a", "b", "c"]);
+        assert_eq!(lp.problem, IsingProblem::new(vec![0.0, 0.0, 0.2], vec![(0, 1, -0.5), (0, 2, 1.0), (1, 2, 1.5)]));
+        let bqm = r#"{"vartype": "BINARY", "linear": {"a": 2.0, "b": 0.0}, "quadratic": [["a", "b", -4.0]], "offset": 1.0}"#;
+        let lp = read_bqm_json(bqm.as_bytes()).unwrap();
+        assert_eq!(lp.problem.energy(&[1, 1]), -1.0);
+        assert_eq!(lp.problem.energy(&[1, -1]), 3.0);
+        assert_eq!(lp.problem.energy(&[-1, -1]), 1.0);
+        let lp2 = LabeledProblem::from(lp.problem.clone());
+        assert_eq!(lp2.labels, vec!["0", "1"]);
+
+        // Errors name the offending line
+        let line_of = |r: Result<LabeledProblem, FormatError>| match r{
+            Err(FormatError::Parse{line, ..}) => line,
+            r => panic!("expected a parse error, got {:?}", r)
+        };
+        assert_eq!(line_of(read_edge_list("0 1 1.0\n\n1 2 x\n".as_bytes())), 3);
+        assert_eq!(line_of(read_edge_list("p ising 3 2\n0 1 1.0\n".as_bytes())), 1);
+        assert_eq!(line_of(read_qubo("p qubo 0 4 1 0\n0 0 1.0\n5 5 1.0\n".as_bytes())), 3);
+        assert_eq!(line_of(read_qubo("c x\np qubo 0 4 2 0\n0 0 1.0\n".as_bytes())), 2);
+        assert_eq!(line_of(read_bqm_json("{\"vartype\": \"SPIN\",\n \"linear\": {\"a\" 1.0}}".as_bytes())), 2);
+        assert!(matches!(read_bqm_json(r#"{"vartype": "SPIN", "quadratic": [[0, 0, 1.0]]}"#.as_bytes()),
+                         Err(FormatError::Invalid(_))));
+    }
+}
@@ -0,0 +1,408 @@
+//! Coupling graphs of lattices, random graphs and annealer hardware
+//!
+//! A `Graph` is a simple undirected graph on the nodes 0..n, with each edge (i, j), i < j,
+//! listed once. Random couplings are drawn on the edges with a `Disorder`, and the result is an
+//! `IsingProblem` with zero local fields. With E = \sum J_{ij} s_i s_j, ferromagnetic bonds are J < 0.
+//!
+//! All lattices have periodic boundaries, with the sites of a unit cell numbered consecutively.
+//! The hardware graphs follow the linear indexing of the Chimera, Pegasus and Zephyr topologies
+//! of D-Wave annealers, so instances embed without relabeling. Index ranges that hold no qubit,
+//! such as the qubits outside the Pegasus fabric, are kept as isolated nodes.
+//! All random quantities are drawn from the generator passed by the caller, so a seeded
+//! generator reproduces an instance exactly.
+
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use crate::ising::IsingProblem;
+
+/// Distribution of the couplings on the edges of a graph
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Disorder{
+    /// J_{ij} = J on every edge
+    Uniform(f64),
+    /// J_{ij} = \pm J with equal probability
+    PlusMinus(f64),
+    /// Gaussian J_{ij} with mean zero and the standard deviation \sigma
+    Gaussian(f64)
+}
+
+impl Disorder{
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64{
+        match *self{
+            Disorder::Uniform(j) => j,
+            Disorder::PlusMinus(j) => if rng.gen::<bool>() { j } else { -j },
+            Disorder::Gaussian(sigma) => sigma * rng.sample::<f64, _>(StandardNormal)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Graph{
+    pub num_nodes: usize,
+    pub edges: Vec<(usize, usize)>
+}
+
+impl Graph{
+    /// Graph of the given edges, which are ordered as (min, max), sorted and deduplicated
+    pub fn from_edges(num_nodes: usize, edges: impl IntoIterator<Item=(usize, usize)>) -> Self{
+        let mut edges : Vec<(usize, usize)> = edges.into_iter().map(|(i, j)|{
+            assert!(i < num_nodes && j < num_nodes, "Graph: edge ({}, {}) out of range", i, j);
+            assert_ne!(i, j, "Graph: self-loop at node {}", i);
+            (i.min(j), i.max(j))
+        }).collect();
+        edges.sort_unstable();
+        edges.dedup();
+        Self{num_nodes, edges}
+    }
+
+    pub fn num_edges(&self) -> usize{
+        self.edges.len()
+    }
+
+    pub fn degrees(&self) -> Vec<usize>{
+        let mut deg = vec![0; self.num_nodes];
+        for &(i, j) in self.edges.iter(){
+            deg[i] += 1;
+            deg[j] += 1;
+        }
+        deg
+    }
+
+    /// Adjacency lists of all nodes
+    pub fn neighbors(&self) -> Vec<Vec<usize>>{
+        let mut adj = vec![Vec::new(); self.num_nodes];
+        for &(i, j) in self.edges.iter(){
+            adj[i].push(j);
+            adj[j].push(i);
+        }
+        adj
+    }
+
+    /// Square lattice of lx x ly sites, with site (x, y) at x + lx y
+    pub fn square(lx: usize, ly: usize) -> Self{
+        assert!(lx >= 3 && ly >= 3, "Graph::square: linear sizes must be at least 3");
+        let idx = |x: usize, y: usize| (x % lx) + lx * (y % ly);
+        let edges = (0..ly).flat_map(|y| (0..lx).flat_map(move |x|{
+            vec![(idx(x, y), idx(x + 1, y)), (idx(x, y), idx(x, y + 1))]
+        }));
+        Self::from_edges(lx * ly, edges)
+    }
+
+    /// Triangular lattice of lx x ly sites: the square lattice with the diagonals (x, y) - (x+1, y+1)
+    pub fn triangular(lx: usize, ly: usize) -> Self{
+        assert!(lx >= 3 && ly >= 3, "Graph::triangular: linear sizes must be at least 3");
+        let idx = |x: usize, y: usize| (x % lx) + lx * (y % ly);
+        let edges = (0..ly).flat_map(|y| (0..lx).flat_map(move |x|{
+            vec![(idx(x, y), idx(x + 1, y)), (idx(x, y), idx(x, y + 1)), (idx(x, y), idx(x + 1, y + 1))]
+        }));
+        Self::from_edges(lx * ly, edges)
+    }
+
+    /// Kagome lattice of lx x ly unit cells of 3 sites, with site s of cell (x, y) at 3 (x + lx y) + s.
+    /// Sites 1 and 2 sit at half of the lattice vectors a_1 and a_2 from site 0.
+    pub fn kagome(lx: usize, ly: usize) -> Self{
+        assert!(lx >= 2 && ly >= 2, "Graph::kagome: linear sizes must be at least 2");
+        let idx = |x: usize, y: usize, s: usize| 3 * ((x % lx) + lx * (y % ly)) + s;
+        let edges = (0..ly).flat_map(|y| (0..lx).flat_map(move |x|{
+            vec![(idx(x, y, 0), idx(x, y, 1)), (idx(x, y, 0), idx(x, y, 2)), (idx(x, y, 1), idx(x, y, 2)),
+                 (idx(x, y, 1), idx(x + 1, y, 0)), (idx(x, y, 2), idx(x, y + 1, 0)),
+                 (idx(x, y, 1), idx(x + 1, y + ly - 1, 2))]
+        }));
+        Self::from_edges(3 * lx * ly, edges)
+    }
+
+    /// Simple cubic lattice of lx x ly x lz sites, with site (x, y, z) at x + lx (y + ly z)
+    pub fn cubic(lx: usize, ly: usize, lz: usize) -> Self{
+        assert!(lx >= 3 && ly >= 3 && lz >= 3, "Graph::cubic: linear sizes must be at least 3");
+        let idx = |x: usize, y: usize, z: usize| (x % lx) + lx * ((y % ly) + ly * (z % lz));
+        let edges = (0..lx * ly * lz).flat_map(|i|{
+            let (x, y, z) = (i % lx, (i / lx) % ly, i / (lx * ly));
+            vec![(i, idx(x + 1, y, z)), (i, idx(x, y + 1, z)), (i, idx(x, y, z + 1))]
+        });
+        Self::from_edges(lx * ly * lz, edges)
+    }
+
+    /// Pyrochlore lattice of l^3 FCC unit cells of 4 sites, with site s of cell (x, y, z) at
+    /// 4 (x + l (y + l z)) + s. Each cell holds an up tetrahedron, and site s > 0 of cell R also
+    /// belongs to the down tetrahedron of site 0 of the cell R + e_s.
+    pub fn pyrochlore(l: usize) -> Self{
+        assert!(l >= 2, "Graph::pyrochlore: the linear size must be at least 2");
+        let cell = |x: usize, y: usize, z: usize| (x % l) + l * ((y % l) + l * (z % l));
+        let edges = (0..l * l * l).flat_map(|c|{
+            let (x, y, z) = (c % l, (c / l) % l, c / (l * l));
+            // Sites of the down tetrahedron with site 0 in this cell
+            let down = [4 * c, 4 * cell(x + l - 1, y, z) + 1, 4 * cell(x, y + l - 1, z) + 2, 4 * cell(x, y, z + l - 1) + 3];
+            let up = [4 * c, 4 * c + 1, 4 * c + 2, 4 * c + 3];
+            let mut e = Vec::with_capacity(12);
+            for tet in [up, down].iter(){
+                for a in 0..4{
+                    for b in a + 1..4{
+                        e.push((tet[a], tet[b]));
+                    }
+                }
+            }
+            e
+        });
+        Self::from_edges(4 * l * l * l, edges)
+    }
+
+    /// Complete graph of n nodes, the graph of the Sherrington-Kirkpatrick model
+    pub fn complete(n: usize) -> Self{
+        Self::from_edges(n, (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))))
+    }
+
+    /// Random d-regular graph of n nodes by the pairing algorithm of Steger and Wormald.
+    /// The stubs of the configuration model are paired one at a time, and a pair that would form
+    /// a self-loop or a multiple edge is redrawn. The pairing only restarts if no valid pair is
+    /// left. The graphs are asymptotically uniform for slowly growing d.
+    pub fn random_regular<R: Rng + ?Sized>(n: usize, d: usize, rng: &mut R) -> Self{
+        assert!(d < n && (n * d) % 2 == 0, "Graph::random_regular: no {}-regular graph of {} nodes", d, n);
+        'pairing: loop{
+            let mut stubs : Vec<usize> = (0..n).flat_map(|i| std::iter::repeat(i).take(d)).collect();
+            let mut adj : Vec<Vec<usize>> = vec![Vec::with_capacity(d); n];
+            let mut edges = Vec::with_capacity(n * d / 2);
+            let mut failures = 0;
+            while !stubs.is_empty(){
+                let a = rng.gen_range(0, stubs.len());
+                let b = rng.gen_range(0, stubs.len());
+                let (i, j) = (stubs[a], stubs[b]);
+                if i == j || adj[i].contains(&j){
+                    failures += 1;
+                    if failures > 4 * stubs.len(){
+                        // Restart if every remaining pair is invalid
+                        let stuck = stubs.iter().all(|&i|
+                            stubs.iter().all(|&j| i == j || adj[i].contains(&j)));
+                        if stuck{
+                            continue 'pairing;
+                        }
+                        failures = 0;
+                    }
+                    continue;
+                }
+                stubs.swap_remove(a.max(b));
+                stubs.swap_remove(a.min(b));
+                adj[i].push(j);
+                adj[j].push(i);
+                edges.push((i.min(j), i.max(j)));
+                failures = 0;
+            }
+            edges.sort_unstable();
+            return Self{num_nodes: n, edges};
+        }
+    }
+
+    /// Chimera graph of m x n unit cells of K_{t,t}. Node (i, j, u, k) of the cell (i, j), with
+    /// u = 0 for vertical and u = 1 for horizontal qubits, is at ((i n + j) 2 + u) t + k.
+    pub fn chimera(m: usize, n: usize, t: usize) -> Self{
+        let idx = |i: usize, j: usize, u: usize, k: usize| ((i * n + j) * 2 + u) * t + k;
+        let mut edges = Vec::new();
+        for i in 0..m{
+            for j in 0..n{
+                for k in 0..t{
+                    for k2 in 0..t{
+                        edges.push((idx(i, j, 0, k), idx(i, j, 1, k2)));
+                    }
+                    if i + 1 < m{
+                        edges.push((idx(i, j, 0, k), idx(i + 1, j, 0, k)));
+                    }
+                    if j + 1 < n{
+                        edges.push((idx(i, j, 1, k), idx(i, j + 1, 1, k)));
+                    }
+                }
+            }
+        }
+        Self::from_edges(2 * m * n * t, edges)
+    }
+
+    /// The Chimera graph C_n of n x n cells of K_{4,4}
+    pub fn chimera_cn(n: usize) -> Self{
+        Self::chimera(n, n, 4)
+    }
+
+    /// Pegasus graph P_m (fabric only, with the standard qubit offsets). Qubit (u, w, k, z),
+    /// with u in {0, 1}, w < m, k < 12, z < m - 1, has the linear index ((u m + w) 12 + k) (m - 1) + z,
+    /// so the graph has 24 m (m - 1) nodes. The 8 (3m - 1)(m - 1) qubits of the fabric are coupled,
+    /// and the remaining nodes are isolated.
+    pub fn pegasus(m: usize) -> Self{
+        assert!(m >= 2, "Graph::pegasus: m must be at least 2");
+        let off0 = [2, 2, 2, 2, 10, 10, 10, 10, 6, 6, 6, 6];
+        let off1 = [6, 6, 6, 6, 2, 2, 2, 2, 10, 10, 10, 10];
+        let (k_start, k_end) = (2, 10);
+        let m1 = m - 1;
+        let in_fabric = |w: usize, k: usize| (w > 0 || k >= k_start) && (w < m1 || k < k_end);
+        let c2i = |u: usize, w: usize, k: usize, z: usize| ((u * m + w) * 12 + k) * m1 + z;
+
+        let mut edges = Vec::new();
+        for u in 0..2{
+            for w in 0..m{
+                for k in (0..12).filter(|&k| in_fabric(w, k)){
+                    // External couplers along the qubit line
+                    for z in 0..m1 - 1{
+                        edges.push((c2i(u, w, k, z), c2i(u, w, k, z + 1)));
+                    }
+                    // Odd couplers between the pairs of parallel qubits
+                    if k % 2 == 0{
+                        for z in 0..m1{
+                            edges.push((c2i(u, w, k, z), c2i(u, w, k + 1, z)));
+                        }
+                    }
+                }
+            }
+        }
+        // Internal couplers between the vertical qubit (0, w, k, z) and the horizontal qubits it crosses
+        for w in 0..m{
+            for kk in 0..12{
+                let k_range = if w == 0 { off1[kk]..12 } else if w == m1 { 0..off1[kk] } else { 0..12 };
+                for k in k_range{
+                    for z in 0..m1{
+                        let w1 = z + (kk < off0[k]) as usize;
+                        let z1 = w - (k < off1[kk]) as usize;
+                        if in_fabric(w, k) && in_fabric(w1, kk){
+                            edges.push((c2i(0, w, k, z), c2i(1, w1, kk, z1)));
+                        }
+                    }
+                }
+            }
+        }
+
+        Self::from_edges(24 * m * m1, edges)
+    }
+
+    /// Zephyr graph Z_{m,t}. Qubit (u, w, k, j, z), with u in {0, 1}, w <= 2m, k < t, j in {0, 1}
+    /// and z < m, is at (((u (2m + 1) + w) t + k) 2 + j) m + z. A qubit spans the two perpendicular
+    /// rows 2z + j and 2z + j + 1, and crosses every perpendicular qubit spanning its own row w.
+    pub fn zephyr(m: usize, t: usize) -> Self{
+        assert!(m >= 1 && t >= 1, "Graph::zephyr: m and t must be positive");
+        let mm = 2 * m + 1;
+        let c2i = |u: usize, w: usize, k: usize, j: usize, z: usize| (((u * mm + w) * t + k) * 2 + j) * m + z;
+        let mut edges = Vec::new();
+        for u in 0..2{
+            for w in 0..mm{
+                for k in 0..t{
+                    for z in 0..m{
+                        // External couplers
+                        for j in 0..2{
+                            if z + 1 < m{
+                                edges.push((c2i(u, w, k, j, z), c2i(u, w, k, j, z + 1)));
+                            }
+                        }
+                        // Odd couplers
+                        edges.push((c2i(u, w, k, 0, z), c2i(u, w, k, 1, z)));
+                        if z > 0{
+                            edges.push((c2i(u, w, k, 0, z), c2i(u, w, k, 1, z - 1)));
+                        }
+                    }
+                }
+            }
+        }
+        // Internal couplers of the vertical qubit (0, 2w + 1 + a (2i - 1), k, j, z) and the
+        // horizontal qubit (1, 2z + 1 + b (2j - 1), h, i, w)
+        for w in 0..m{
+            for z in 0..m{
+                for (i, j, a, b) in (0..16).map(|c| (c & 1, (c >> 1) & 1, (c >> 2) & 1, (c >> 3) & 1)){
+                    let wv = 2 * w + 1 + a * (2 * i) - a;
+                    let wh = 2 * z + 1 + b * (2 * j) - b;
+                    for k in 0..t{
+                        for h in 0..t{
+                            edges.push((c2i(0, wv, k, j, z), c2i(1, wh, h, i, w)));
+                        }
+                    }
+                }
+            }
+        }
+        Self::from_edges(4 * t * m * mm, edges)
+    }
+
+    /// Random couplings on the edges of the graph
+    pub fn couplings<R: Rng + ?Sized>(&self, disorder: Disorder, rng: &mut R) -> Vec<(usize, usize, f64)>{
+        self.edges.iter().map(|&(i, j)| (i, j, disorder.sample(rng))).collect()
+    }
+
+    /// Ising problem with random couplings and zero local fields
+    pub fn ising<R: Rng + ?Sized>(&self, disorder: Disorder, rng: &mut R) -> IsingProblem{
+        IsingProblem::new(vec![0.0; self.num_nodes], self.couplings(disorder, rng))
+    }
+
+    /// Ising problem with a planted ground state: a uniformly random configuration s^*, and the
+    /// couplings J_{ij} = -|J| s^*_i s^*_j with |J| drawn from `disorder`. Every bond is satisfied
+    /// by s^*, which is therefore a ground state with the energy -\sum |J_{ij}|.
+    ///
+    /// Returns the problem and s^*.
+    pub fn planted_ising<R: Rng + ?Sized>(&self, disorder: Disorder, rng: &mut R) -> (IsingProblem, Vec<i8>){
+        let s : Vec<i8> = (0..self.num_nodes).map(|_| if rng.gen::<bool>() { 1 } else { -1 }).collect();
+        let couplings = self.edges.iter()
+            .map(|&(i, j)| (i, j, -disorder.sample(rng).abs() * (s[i] * s[j]) as f64))
+            .collect();
+        (IsingProblem::new(vec![0.0; self.num_nodes], couplings), s)
+    }
+}
+
+/// Sherrington-Kirkpatrick problem of n spins, with Gaussian couplings of variance 1 / n
+pub fn sherrington_kirkpatrick<R: Rng + ?Sized>(n: usize, rng: &mut R) -> IsingProblem{
+    Graph::complete(n).ising(Disorder::Gaussian(1.0 / (n as f64).sqrt()), rng)
+}
+
+#[cfg(test)]
+mod tests{
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    use super::{sherrington_kirkpatrick, Disorder, Graph};
+
+    #[test]
+    fn test_graph_generators(){
+        let regular = |g: &Graph, n: usize, d: usize|{
+            assert_eq!(g.num_nodes, n);
+            assert!(g.degrees().iter().all(|&k| k == d), "{:?}", g.degrees());
+            assert_eq!(g.num_edges(), n * d / 2);
+        };
+        regular(&Graph::square(4, 3), 12, 4);
+        regular(&Graph::triangular(3, 5), 15, 6);
+        regular(&Graph::kagome(2, 3), 18, 4);
+        regular(&Graph::cubic(3, 4, 3), 36, 6);
+        regular(&Graph::pyrochlore(2), 32, 6);
+        regular(&Graph::complete(7), 7, 6);
+        let mut rng = Xoshiro256Plus::seed_from_u64(5);
+        for &(n, d) in [(50, 3), (1000, 10), (30, 29)].iter(){
+            let g = Graph::random_regular(n, d, &mut rng);
+            regular(&g, n, d);
+            assert!(g.edges.windows(2).all(|w| w[0] < w[1]) && g.edges.iter().all(|&(i, j)| i < j));
+        }
+
+        // Published qubit and coupler counts of the hardware graphs
+        let c16 = Graph::chimera_cn(16);
+        assert_eq!((c16.num_nodes, c16.num_edges()), (2048, 6016));
+        let p6 = Graph::pegasus(6);
+        assert_eq!((p6.num_nodes, p6.num_edges()), (720, 4484));
+        assert_eq!(p6.degrees().iter().filter(|&&d| d > 0).count(), 680);
+        assert_eq!(*p6.degrees().iter().max().unwrap(), 15);
+        let p16 = Graph::pegasus(16);
+        assert_eq!((p16.num_nodes, p16.num_edges()), (5760, 40484));
+        assert_eq!(p16.degrees().iter().filter(|&&d| d > 0).count(), 5640);
+        let z4 = Graph::zephyr(4, 4);
+        assert_eq!((z4.num_nodes, z4.num_edges()), (576, 5032));
+        assert_eq!(*z4.degrees().iter().max().unwrap(), 20);
+        let z15 = Graph::zephyr(15, 4);
+        assert_eq!((z15.num_nodes, z15.num_edges()), (7440, 71736));
+
+        // Reproducible disorder
+        let sk1 = sherrington_kirkpatrick(20, &mut Xoshiro256Plus::seed_from_u64(9));
+        let sk2 = sherrington_kirkpatrick(20, &mut Xoshiro256Plus::seed_from_u64(9));
+        assert_eq!(sk1, sk2);
+        assert_eq!(sk1.couplings.len(), 190);
+        let pm = p6.ising(Disorder::PlusMinus(1.0), &mut rng);
+        assert!(pm.couplings.iter().all(|c| c.2.abs() == 1.0));
+
+        // The planted state is a local minimum with the energy -\sum |J|
+        let (problem, s) = Graph::square(5, 5).planted_ising(Disorder::Gaussian(1.0), &mut rng);
+        let e0 = problem.energy(&s);
+        let e_sum : f64 = problem.couplings.iter().map(|c| c.2.abs()).sum();
+        assert!((e0 + e_sum).abs() < 1.0e-12);
+        for i in 0..s.len(){
+            let mut s1 = s.clone();
+            s1[i] = -s1[i];
+            assert!(problem.energy(&s1) >= e0);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Quantum annealing Hamiltonians
+//!
+//!     H(t) = -A(s) \sum_i X_i + B(s) H_P,      s = s(t)
+//!
+//! where H_P is a problem Hamiltonian in terms of Pauli matrices and A(s), B(s) are the annealing
+//! schedule curves, in angular frequency units (\hbar \equiv 1). The annealing path s(t) is
+//! piecewise linear, which covers linear, paused, quenched and reverse anneals.
+//!
+//! The semiclassical limit is taken on the spin operators S_i = \sigma_i / 2, so each K-body term
+//! of H(t) is rescaled by 2^K when the Hamiltonian is constructed: the transverse field becomes
+//! 2 A(s), the local fields of H_P become 2 h_i and the couplings become 4 J_{ij}.
+//! The single-qubit coupling \eta of the bath must likewise be rescaled with `spin_eta`.
+
+use ndarray::{ArrayView1, ArrayViewMut1};
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use super::SparseCouplingHamiltonian;
+
+/// Natural cubic spline through the knots (x_k, y_k). Evaluation is clamped to the end values
+/// outside of [x_0, x_n].
+#[derive(Clone, Debug)]
+pub struct CubicSpline{
+    x: Vec<f64>,
+    y: Vec<f64>,
+    y2: Vec<f64>
+}
+
+impl CubicSpline{
+    pub fn new(x: &[f64], y: &[f64]) -> Self{
+        let n = x.len();
+        assert_eq!(n, y.len(), "CubicSpline: mismatching number of knots");
+        assert!(n >= 2, "CubicSpline: at least two knots are required");
+        assert!(x.windows(2).all(|w| w[1] > w[0]), "CubicSpline: knots must be strictly increasing");
+
+        // Tridiagonal system for the second derivatives, with y''_0 = y''_{n-1} = 0
+        let mut y2 = vec![0.0; n];
+        let mut u = vec![0.0; n];
+        for i in 1..n-1{
+            let sig = (x[i] - x[i-1]) / (x[i+1] - x[i-1]);
+            let p = sig * y2[i-1] + 2.0;
+            y2[i] = (sig - 1.0) / p;
+            let d = (y[i+1] - y[i]) / (x[i+1] - x[i]) - (y[i] - y[i-1]) / (x[i] - x[i-1]);
+            u[i] = (6.0 * d / (x[i+1] - x[i-1]) - sig * u[i-1]) / p;
+        }
+        y2[n-1] = 0.0;
+        for k in (0..n-1).rev(){
+            y2[k] = y2[k] * y2[k+1] + u[k];
+        }
+
+        Self{x: x.to_vec(), y: y.to_vec(), y2}
+    }
+
+    pub fn eval(&self, x: f64) -> f64{
+        assert!(!x.is_nan(), "CubicSpline: cannot evaluate at NaN");
+        let n = self.x.len();
+        if x <= self.x[0]{
+            return self.y[0];
+        }
+        if x >= self.x[n-1]{
+            return self.y[n-1];
+        }
+        // Index of the interval [x_k, x_{k+1}] containing x
+        let k = match self.x.binary_search_by(|xi| xi.partial_cmp(&x).unwrap()){
+            Ok(k) => return self.y[k],
+            Err(k) => k - 1
+        };
+        let h = self.x[k+1] - self.x[k];
+        let a = (self.x[k+1] - x) / h;
+        let b = (x - self.x[k]) / h;
+
+        a * self.y[k] + b * self.y[k+1]
+            + ((a*a*a - a) * self.y2[k] + (b*b*b - b) * self.y2[k+1]) * (h*h) / 6.0
+    }
+}
+
+enum ScheduleCurve{
+    Spline(CubicSpline),
+    Analytic(Box<dyn Fn(f64) -> f64 + Send + Sync>)
+}
+
+impl ScheduleCurve{
+    fn eval(&self, s: f64) -> f64{
+        match self{
+            ScheduleCurve::Spline(sp) => sp.eval(s),
+            ScheduleCurve::Analytic(f) => f(s)
+        }
+    }
+}
+
+/// The annealing schedule curves A(s) and B(s) for s \in [0, 1]
+pub struct AnnealingSchedule{
+    a: ScheduleCurve,
+    b: ScheduleCurve
+}
+
+impl AnnealingSchedule{
+    /// Schedule interpolated by cubic splines through the tabulated values A(s_k), B(s_k)
+    pub fn tabulated(s: &[f64], a: &[f64], b: &[f64]) -> Self{
+        Self{a: ScheduleCurve::Spline(CubicSpline::new(s, a)),
+             b: ScheduleCurve::Spline(CubicSpline::new(s, b))}
+    }
+
+    /// Schedule with analytic curves A(s) and B(s)
+    pub fn analytic<Fa, Fb>(a: Fa, b: Fb) -> Self
+    where Fa: Fn(f64) -> f64 + Send + Sync + 'static,
+          Fb: Fn(f64) -> f64 + Send + Sync + 'static
+    {
+        Self{a: ScheduleCurve::Analytic(Box::new(a)), b: ScheduleCurve::Analytic(Box::new(b))}
+    }
+
+    /// The linear interpolation  A(s) = a_0 (1 - s),  B(s) = b_1 s
+    pub fn linear(a0: f64, b1: f64) -> Self{
+        Self::analytic(move |s| a0 * (1.0 - s), move |s| b1 * s)
+    }
+
+    pub fn a(&self, s: f64) -> f64{
+        self.a.eval(s)
+    }
+
+    pub fn b(&self, s: f64) -> f64{
+        self.b.eval(s)
+    }
+}
+
+/// The single-qubit bath coupling \eta rescaled for the semiclassical spin-1/2 limit
+pub fn spin_eta(eta: f64) -> f64{
+    2.0 * eta
+}
+
+/// Piecewise linear annealing path s(t) through the breakpoints (t_k, s_k).
+/// s(t) is held at s_0 before t_0 and at the final s after the last breakpoint.
+#[derive(Clone, Debug)]
+pub struct AnnealPath{
+    t: Vec<f64>,
+    s: Vec<f64>
+}
+
+impl AnnealPath{
+    pub fn new(breakpoints: &[(f64, f64)]) -> Self{
+        assert!(!breakpoints.is_empty(), "AnnealPath: at least one breakpoint is required");
+        assert!(breakpoints.windows(2).all(|w| w[1].0 > w[0].0),
+                "AnnealPath: breakpoint times must be strictly increasing");
+        assert!(breakpoints.iter().all(|&(_, s)| (0.0..=1.0).contains(&s)),
+                "AnnealPath: s must lie in [0, 1]");
+        let (t, s) = breakpoints.iter().cloned().unzip();
+
+        Self{t, s}
+    }
+
+    /// Forward anneal  s = t / t_a
+    pub fn linear(t_a: f64) -> Self{
+        Self::new(&[(0.0, 0.0), (t_a, 1.0)])
+    }
+
+    /// Forward anneal at the rate 1/t_a with a pause of duration t_p at s_p
+    pub fn pause(t_a: f64, s_p: f64, t_p: f64) -> Self{
+        let t1 = s_p * t_a;
+        Self::new(&[(0.0, 0.0), (t1, s_p), (t1 + t_p, s_p), (t_a + t_p, 1.0)])
+    }
+
+    /// Forward anneal at the rate 1/t_a up to s_q, followed by a quench to s = 1 over t_q
+    pub fn quench(t_a: f64, s_q: f64, t_q: f64) -> Self{
+        let t1 = s_q * t_a;
+        Self::new(&[(0.0, 0.0), (t1, s_q), (t1 + t_q, 1.0)])
+    }
+
+    /// Reverse anneal from s = 1 down to s_r over t_r, a pause of t_p at s_r,
+    /// and the return to s = 1 over t_r
+    pub fn reverse(s_r: f64, t_r: f64, t_p: f64) -> Self{
+        Self::new(&[(0.0, 1.0), (t_r, s_r), (t_r + t_p, s_r), (2.0 * t_r + t_p, 1.0)])
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item=(f64, f64)> + '_{
+        self.t.iter().cloned().zip(self.s.iter().cloned())
+    }
+
+    pub fn start_time(&self) -> f64{
+        self.t[0]
+    }
+
+    pub fn end_time(&self) -> f64{
+        *self.t.last().unwrap()
+    }
+
+    pub fn duration(&self) -> f64{
+        self.end_time() - self.start_time()
+    }
+
+    pub fn s(&self, t: f64) -> f64{
+        let n = self.t.len();
+        if t <= self.t[0]{
+            return self.s[0];
+        }
+        if t >= self.t[n-1]{
+            return self.s[n-1];
+        }
+        let k = self.t.iter().position(|&tk| tk > t).unwrap() - 1;
+        let u = (t - self.t[k]) / (self.t[k+1] - self.t[k]);
+
+        self.s[k] + u * (self.s[k+1] - self.s[k])
+    }
+}
+
+/// Annealing Hamiltonian  H(t) = -A(s(t)) \sum_i X_i + B(s(t)) H_P  with the spin-1/2
+/// rescaling already applied
+pub struct AnnealingHamiltonian{
+    schedule: AnnealingSchedule,
+    problem: SparseCouplingHamiltonian,
+    path: AnnealPath,
+    // 1 on the lanes holding spins, 0 on the padding of the last chunk
+    lane_mask: Vec<Aligned4xf64>
+}
+
+impl AnnealingHamiltonian{
+    /// Construct from a schedule, a Pauli problem Hamiltonian and the total annealing time t_a.
+    /// The problem Hamiltonian is rescaled by 2^K for each K-body term.
+    pub fn new(schedule: AnnealingSchedule, problem: &SparseCouplingHamiltonian, t_a: f64) -> Self{
+        assert!(t_a > 0.0, "AnnealingHamiltonian: the annealing time must be positive");
+        Self::with_path(schedule, problem, AnnealPath::linear(t_a))
+    }
+
+    /// Construct with a general annealing path s(t)
+    pub fn with_path(schedule: AnnealingSchedule, problem: &SparseCouplingHamiltonian, path: AnnealPath) -> Self{
+        let mut problem = problem.clone();
+        problem.scale(2.0, 4.0);
+        let n = problem.num_spins();
+        let lane_mask = (0..problem.num_chunks()).map(|c|{
+            let mut x = Aligned4xf64::from(0.0);
+            for (l, xl) in x.dat.iter_mut().enumerate(){
+                *xl = if 4 * c + l < n { 1.0 } else { 0.0 };
+            }
+            x
+        }).collect();
+
+        Self{schedule, problem, path, lane_mask}
+    }
+
+    pub fn annealing_time(&self) -> f64{
+        self.path.duration()
+    }
+
+    pub fn path(&self) -> &AnnealPath{
+        &self.path
+    }
+
+    pub fn schedule(&self) -> &AnnealingSchedule{
+        &self.schedule
+    }
+
+    /// The rescaled problem Hamiltonian 2^K H_P
+    pub fn problem(&self) -> &SparseCouplingHamiltonian{
+        &self.problem
+    }
+
+    pub fn num_spins(&self) -> usize{
+        self.problem.num_spins()
+    }
+
+    /// Anneal fraction s(t)
+    pub fn s(&self, t: f64) -> f64{
+        self.path.s(t)
+    }
+
+    /// Evaluate the local fields  2 A(s) e_x + B(s) h_P  of a row of spins
+    pub fn local_fields(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        let s = self.s(t);
+        let a = Aligned4xf64::from(2.0 * self.schedule.a(s));
+        let b = Aligned4xf64::from(self.schedule.b(s));
+        self.problem.local_fields(t, m, h);
+        for (hc, &mask) in h.iter_mut().zip(self.lane_mask.iter()){
+            *hc *= b;
+            hc[0] += a * mask;
+        }
+    }
+
+    /// The Hamiltonian closure of `spin_langevin_step`
+    pub fn haml_fn(&self) -> impl Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync + '_{
+        move |t, m, h| self.local_fields(t, m, h)
+    }
+
+    /// Semiclassical energy of a row of spins at time t
+    pub fn energy(&self, t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        let s = self.s(t);
+        let mut mx = Aligned4xf64::from(0.0);
+        for (mc, &mask) in m.iter().zip(self.lane_mask.iter()){
+            mx += mc[0] * mask;
+        }
+
+        self.schedule.b(s) * self.problem.energy(m) - 2.0 * self.schedule.a(s) * 4.0 * mx.mean_reduce()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use ndarray::Array2;
+    use num_traits::Zero;
+    use simd_phys::r3::Vector3d4xf64;
+
+    use super::{AnnealingHamiltonian, AnnealingSchedule, CubicSpline};
+    use crate::hamiltonian::SparseCouplingHamiltonian;
+
+    #[test]
+    fn test_annealing_fields(){
+        // A natural spline reproduces linear schedules exactly and smooth data closely
+        let s : Vec<f64> = (0..=20).map(|k| k as f64 / 20.0).collect();
+        let y : Vec<f64> = s.iter().map(|&s| (2.0 * s).sin()).collect();
+        let sp = CubicSpline::new(&s, &y);
+        for k in 0..100{
+            let x = 0.2 + 0.6 * k as f64 / 100.0;
+            assert!((sp.eval(x) - (2.0 * x).sin()).abs() < 1.0e-5);
+        }
+
+        let a : Vec<f64> = s.iter().map(|&s| 3.0 * (1.0 - s)).collect();
+        let b : Vec<f64> = s.iter().map(|&s| 2.0 * s).collect();
+        let problem = SparseCouplingHamiltonian::ising(&[0.5, -0.5], &[(0, 1, 1.0)]);
+        let tab = AnnealingHamiltonian::new(AnnealingSchedule::tabulated(&s, &a, &b), &problem, 10.0);
+        let lin = AnnealingHamiltonian::new(AnnealingSchedule::linear(3.0, 2.0), &problem, 10.0);
+
+        // Four replicas of the two spins, one per row
+        let mut m : Array2<Vector3d4xf64> = Array2::from_elem((4, 1), Zero::zero());
+        for (r, &(mx0, mz0, mz1)) in [(0.0, 1.0, 1.0), (0.0, -1.0, 1.0), (1.0, 0.0, 0.0), (0.5, 0.5, -0.5)]
+            .iter().enumerate()
+        {
+            m[(r, 0)][0].dat[0] = mx0;
+            m[(r, 0)][2].dat[0] = mz0;
+            m[(r, 0)][2].dat[1] = mz1;
+        }
+        let mut h1 = Array2::from_elem((4, 1), Zero::zero());
+        let mut h2 = Array2::from_elem((4, 1), Zero::zero());
+        for &t in [0.0, 3.3, 7.5, 10.0].iter(){
+            let s = t / 10.0;
+            for r in 0..4{
+                tab.local_fields(t, &m.row(r), &mut h1.row_mut(r));
+                lin.local_fields(t, &m.row(r), &mut h2.row_mut(r));
+                for c in 0..3{
+                    for l in 0..4{
+                        assert!((h1[(r, 0)][c].dat[l] - h2[(r, 0)][c].dat[l]).abs() < 1.0e-12);
+                    }
+                }
+                // Rescaled transverse field and Pauli problem terms, with inert padding lanes
+                assert!((h2[(r, 0)][0].dat[0] - 6.0 * (1.0 - s)).abs() < 1.0e-12);
+                assert_eq!(h2[(r, 0)][0].dat[2], 0.0);
+                let hz = -2.0 * s * (2.0 * 0.5 + 4.0 * m[(r, 0)][2].dat[1]);
+                assert!((h2[(r, 0)][2].dat[0] - hz).abs() < 1.0e-12);
+            }
+        }
+        assert!((lin.energy(5.0, &m.row(0)) - 4.0).abs() < 1.0e-12);
+        assert!((lin.energy(5.0, &m.row(2)) + 3.0).abs() < 1.0e-12);
+    }
+}
@@ -0,0 +1,22 @@
+//! Hamiltonians that evaluate the local fields of `spin_langevin_step` directly on the chunked
+//! `Vector3d4xf64` layout.
+//!
+//! A row of spins is an `ArrayView1<Vector3d4xf64>` of chunks as packed by `xyz_to_array_chunks`,
+//! where spin i is held in lane i % 4 of chunk i / 4, and the padding lanes of the last chunk are
+//! inert. Each row of a spin array is an independent replica of the system.
+//!
+//! Sign convention: the local field is the effective field  h_i = -\partial E / \partial m_i,
+//! so that the dissipative term of the Spin-Langevin equation relaxes the spins towards lower
+//! energies.
+
+pub mod annealing;
+pub mod dipolar;
+pub mod schedule;
+pub mod sparse;
+pub mod terms;
+
+pub use annealing::{AnnealPath, AnnealingHamiltonian, AnnealingSchedule};
+pub use dipolar::DipolarField;
+pub use schedule::{ScheduleError, ScheduleTable};
+pub use sparse::{CouplingTensor, SparseCouplingHamiltonian};
+pub use terms::{CompositeHamiltonian, CubicAnisotropy, DmiTerm, FieldTerm, UniaxialAnisotropy, Zeeman};
@@ -0,0 +1,199 @@
+//! Loading annealing schedules from CSV tables
+//!
+//! Schedule files list the columns s, A(s), B(s) with A and B given as frequencies in GHz, i.e.
+//! as E / h. The Spin-Langevin equation is integrated with \hbar \equiv 1 and time in ns, so the
+//! schedule curves are converted to angular frequencies
+//!     A [rad / ns] = 2 \pi A [GHz]
+//! when loaded. Any additional columns are ignored. Lines that are empty or begin with '#'
+//! are skipped, as is a header line before the first row in which no field is numeric.
+//!
+//! Some vendors define the annealing Hamiltonian with the convention
+//! H(s) = -A(s)/2 \sum_i X_i + B(s)/2 H_P. Such tables should be scaled by 1/2 with
+//! `ScheduleTable::scale` before use.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use super::AnnealingSchedule;
+
+/// Conversion factor from GHz to rad / ns
+pub const GHZ_TO_RAD_PER_NS: f64 = 2.0 * PI;
+
+#[derive(Debug)]
+pub enum ScheduleError{
+    Io(io::Error),
+    /// A malformed or invalid line of the table, numbered from 1
+    Parse{line: usize, msg: String},
+    /// The table has fewer than two rows
+    Empty
+}
+
+impl fmt::Display for ScheduleError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            ScheduleError::Io(e) => write!(f, "schedule I/O error: {}", e),
+            ScheduleError::Parse{line, msg} => write!(f, "schedule line {}: {}", line, msg),
+            ScheduleError::Empty => write!(f, "schedule table needs at least two rows")
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self{
+            ScheduleError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ScheduleError{
+    fn from(e: io::Error) -> Self{
+        ScheduleError::Io(e)
+    }
+}
+
+/// Tabulated schedule curves A(s_k), B(s_k) in angular frequency units (rad / ns)
+#[derive(Clone, Debug)]
+pub struct ScheduleTable{
+    pub s: Vec<f64>,
+    pub a: Vec<f64>,
+    pub b: Vec<f64>
+}
+
+impl ScheduleTable{
+    /// Read a CSV table with A and B in GHz. The rows must have strictly increasing s in [0, 1],
+    /// non-increasing A(s) and non-decreasing B(s).
+    pub fn read_csv<Rd: BufRead>(reader: Rd) -> Result<Self, ScheduleError>{
+        let mut table = ScheduleTable{s: Vec::new(), a: Vec::new(), b: Vec::new()};
+        let mut header_seen = false;
+        for (k, line) in reader.lines().enumerate(){
+            let line = line?;
+            let lineno = k + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+            let cols : Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+            let vals : Result<Vec<f64>, _> = cols.iter().take(3).map(|c| c.parse::<f64>()).collect();
+            let vals = match vals{
+                Ok(v) if v.len() == 3 => v,
+                Ok(_) => return Err(ScheduleError::Parse{
+                    line: lineno, msg: format!("expected 3 columns, found {}", cols.len())}),
+                Err(e) => {
+                    // A header line has no numeric fields, and only precedes the first row
+                    if !header_seen && table.s.is_empty() && cols.iter().all(|c| c.parse::<f64>().is_err()){
+                        header_seen = true;
+                        continue;
+                    }
+                    return Err(ScheduleError::Parse{line: lineno, msg: format!("{}", e)});
+                }
+            };
+            let (s, a, b) = (vals[0], vals[1] * GHZ_TO_RAD_PER_NS, vals[2] * GHZ_TO_RAD_PER_NS);
+            table.validate_row(lineno, s, a, b)?;
+            table.s.push(s);
+            table.a.push(a);
+            table.b.push(b);
+        }
+        if table.s.len() < 2{
+            return Err(ScheduleError::Empty);
+        }
+
+        Ok(table)
+    }
+
+    /// Load a CSV schedule file with A and B in GHz
+    pub fn load_csv<P: AsRef<Path>>(path: P) -> Result<Self, ScheduleError>{
+        Self::read_csv(BufReader::new(File::open(path)?))
+    }
+
+    fn validate_row(&self, line: usize, s: f64, a: f64, b: f64) -> Result<(), ScheduleError>{
+        let err = |msg: &str| Err(ScheduleError::Parse{line, msg: msg.to_string()});
+        if !(s.is_finite() && a.is_finite() && b.is_finite()){
+            return err("non-finite value");
+        }
+        if !(0.0..=1.0).contains(&s){
+            return err("s must lie in [0, 1]");
+        }
+        if a < 0.0 || b < 0.0{
+            return err("A(s) and B(s) must be non-negative");
+        }
+        if let Some(&s_prev) = self.s.last(){
+            if s <= s_prev{
+                return err("s must be strictly increasing");
+            }
+            if a > *self.a.last().unwrap(){
+                return err("A(s) must be non-increasing");
+            }
+            if b < *self.b.last().unwrap(){
+                return err("B(s) must be non-decreasing");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Multiply both schedule curves by `factor`
+    pub fn scale(&mut self, factor: f64){
+        self.a.iter_mut().for_each(|a| *a *= factor);
+        self.b.iter_mut().for_each(|b| *b *= factor);
+    }
+
+    /// Cubic spline interpolated annealing schedule
+    pub fn to_schedule(&self) -> AnnealingSchedule{
+        AnnealingSchedule::tabulated(&self.s, &self.a, &self.b)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::f64::consts::PI;
+
+    use super::{ScheduleError, ScheduleTable};
+    use crate::hamiltonian::{AnnealPath, AnnealingHamiltonian, SparseCouplingHamiltonian};
+
+    #[test]
+    fn test_schedule_csv(){
+        let csv = "s,A(s) (GHz),B(s) (GHz),C (normalized)\n\
+                   0.0, 2.0, 0.0, 0.0\n\
+                   # comment\n\
+                   0.5, 1.0, 1.0, 0.5\n\
+                   1.0, 0.0, 2.0, 1.0\n";
+        let table = ScheduleTable::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(table.s, vec![0.0, 0.5, 1.0]);
+        assert!((table.a[0] - 4.0 * PI).abs() < 1.0e-12);
+        assert!((table.b[1] - 2.0 * PI).abs() < 1.0e-12);
+
+        let bad = "s,A,B\n0.0, 2.0, 0.0\n0.5, 1.0, 1.0\n0.4, 0.5, 1.5\n";
+        match ScheduleTable::read_csv(bad.as_bytes()){
+            Err(ScheduleError::Parse{line, ..}) => assert_eq!(line, 4),
+            r => panic!("expected a parse error, got {:?}", r)
+        }
+        // A malformed first row is not a header
+        let bad = "0.0, x, 0.0\n0.5, 1.0, 1.0\n1.0, 0.0, 2.0\n";
+        match ScheduleTable::read_csv(bad.as_bytes()){
+            Err(ScheduleError::Parse{line, ..}) => assert_eq!(line, 1),
+            r => panic!("expected a parse error, got {:?}", r)
+        }
+        let bad = "0.0, 2.0, 0.0\n0.5, x, 1.0\n";
+        match ScheduleTable::read_csv(bad.as_bytes()){
+            Err(ScheduleError::Parse{line, ..}) => assert_eq!(line, 2),
+            r => panic!("expected a parse error, got {:?}", r)
+        }
+
+        // Reverse anneal to s = 0.5 with a pause
+        let path = AnnealPath::reverse(0.5, 2.0, 1.0);
+        assert_eq!(path.duration(), 5.0);
+        assert!((path.s(1.0) - 0.75).abs() < 1.0e-12);
+        assert!((path.s(2.5) - 0.5).abs() < 1.0e-12);
+        assert!((path.s(4.0) - 0.75).abs() < 1.0e-12);
+        assert_eq!(path.s(10.0), 1.0);
+
+        let problem = SparseCouplingHamiltonian::ising(&[1.0], &[]);
+        let ham = AnnealingHamiltonian::with_path(table.to_schedule(), &problem, path);
+        assert!((ham.schedule().a(ham.s(2.5)) - 2.0 * PI).abs() < 1.0e-12);
+    }
+}
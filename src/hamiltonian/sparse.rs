@@ -0,0 +1,384 @@
+//! Sparse two-body coupling Hamiltonians
+//!
+//!     E(m) = \sum_i h_i . m_i + \sum_{(i, j)} m_i^T J_{ij} m_j
+//!
+//! where each coupled pair (i, j) appears once in the sum, with J_{ji} = J_{ij}^T.
+//!
+//! The couplings are assembled in compressed sparse row (CSR) form, with both J_{ij} in row i and
+//! J_{ji} in row j, and stored in sliced ELLPACK form matching the chunk layout: the rows of the
+//! four spins of a chunk are interleaved entry by entry and padded to the largest degree in the
+//! chunk. Each slot gathers the four neighbors m_j into a `Vector3d4xf64` and multiplies them by
+//! the packed couplings of the four lanes, so the local fields of a whole chunk are accumulated
+//! with 4xf64 operations.
+
+use nalgebra::{Matrix3, Vector3};
+use ndarray::{ArrayView1, ArrayViewMut1};
+use num_traits::Zero;
+use simd_phys::r3::{Matrix3d4xf64, Vector3d4xf64};
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::num_chunks;
+
+/// Coupling tensor J_{ij} of a single pair of spins
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CouplingTensor{
+    /// Ising coupling  J m_i^z m_j^z
+    Ising(f64),
+    /// Diagonal XYZ Heisenberg coupling  J_x m_i^x m_j^x + J_y m_i^y m_j^y + J_z m_i^z m_j^z
+    Xyz(Vector3<f64>),
+    /// General anisotropic coupling  m_i^T J m_j
+    Anisotropic(Matrix3<f64>)
+}
+
+impl CouplingTensor{
+    fn to_matrix(self) -> Matrix3<f64>{
+        match self{
+            CouplingTensor::Ising(j) => Matrix3::new(0.0, 0.0, 0.0,
+                                                     0.0, 0.0, 0.0,
+                                                     0.0, 0.0, j),
+            CouplingTensor::Xyz(j) => Matrix3::from_diagonal(&j),
+            CouplingTensor::Anisotropic(j) => j
+        }
+    }
+}
+
+/// Packed coupling values of each slot, specialized by the kind of coupling tensor
+#[derive(Clone, Debug)]
+enum CouplingValues{
+    Ising(Vec<Aligned4xf64>),
+    Xyz(Vec<Vector3d4xf64>),
+    Anisotropic(Vec<Matrix3d4xf64>)
+}
+
+/// Gather the neighbors idx[l] of the four lanes into a single chunk
+#[inline]
+fn gather(m: &ArrayView1<Vector3d4xf64>, idx: &[usize; 4]) -> Vector3d4xf64{
+    let mut v : Vector3d4xf64 = Zero::zero();
+    for (l, &j) in idx.iter().enumerate(){
+        let mj = &m[j / 4];
+        for a in 0..3{
+            v[a].dat[l] = mj[a].dat[j % 4];
+        }
+    }
+    v
+}
+
+/// Sparse Ising, Heisenberg or anisotropic two-body Hamiltonian with per-spin local fields h_i
+#[derive(Clone, Debug)]
+pub struct SparseCouplingHamiltonian{
+    n: usize,
+    chunk_fields: Vec<Vector3d4xf64>,
+    row_len: Vec<usize>,
+    chunk_ptr: Vec<usize>,
+    slot_idx: Vec<[usize; 4]>,
+    values: CouplingValues
+}
+
+impl SparseCouplingHamiltonian{
+    /// Ising Hamiltonian  E = \sum_i h_i m_i^z + \sum_{(i,j)} J_{ij} m_i^z m_j^z
+    /// Repeated pairs are summed.
+    pub fn ising(h: &[f64], couplings: &[(usize, usize, f64)]) -> Self{
+        let fields : Vec<Vector3<f64>> = h.iter().map(|&hi| Vector3::new(0.0, 0.0, hi)).collect();
+        let c : Vec<(usize, usize, CouplingTensor)> = couplings.iter()
+            .map(|&(i, j, jij)| (i, j, CouplingTensor::Ising(jij))).collect();
+        Self::build(&fields, &c, 0)
+    }
+
+    /// XYZ Heisenberg Hamiltonian with diagonal coupling tensors (J_x, J_y, J_z) and
+    /// local fields h_i. Repeated pairs are summed.
+    pub fn heisenberg(h: &[Vector3<f64>], couplings: &[(usize, usize, Vector3<f64>)]) -> Self{
+        let c : Vec<(usize, usize, CouplingTensor)> = couplings.iter()
+            .map(|&(i, j, jij)| (i, j, CouplingTensor::Xyz(jij))).collect();
+        Self::build(h, &c, 1)
+    }
+
+    /// Anisotropic Hamiltonian with general coupling tensors  m_i^T J_{ij} m_j  and
+    /// local fields h_i. Repeated pairs are summed.
+    pub fn anisotropic(h: &[Vector3<f64>], couplings: &[(usize, usize, Matrix3<f64>)]) -> Self{
+        let c : Vec<(usize, usize, CouplingTensor)> = couplings.iter()
+            .map(|&(i, j, jij)| (i, j, CouplingTensor::Anisotropic(jij))).collect();
+        Self::build(h, &c, 2)
+    }
+
+    /// Hamiltonian with an arbitrary mix of coupling tensors. The tensors are stored in the
+    /// most specific form that represents all of them.
+    pub fn from_tensors(h: &[Vector3<f64>], couplings: &[(usize, usize, CouplingTensor)]) -> Self{
+        let kind = couplings.iter().map(|(_, _, c)| match c{
+            CouplingTensor::Ising(_) => 0,
+            CouplingTensor::Xyz(_) => 1,
+            CouplingTensor::Anisotropic(_) => 2
+        }).max().unwrap_or(0);
+        Self::build(h, couplings, kind)
+    }
+
+    /// Assemble the CSR rows and pack them in sliced ELLPACK form, with the coupling kind
+    /// 0 (Ising), 1 (XYZ) or 2 (anisotropic)
+    fn build(fields: &[Vector3<f64>], couplings: &[(usize, usize, CouplingTensor)], kind: u8) -> Self{
+        let n = fields.len();
+        let n_ch = num_chunks(n);
+
+        // Merge repeated pairs, oriented as i < j
+        let mut pairs : Vec<(usize, usize, Matrix3<f64>)> = Vec::with_capacity(couplings.len());
+        for &(i, j, c) in couplings.iter(){
+            assert!(i < n && j < n, "SparseCouplingHamiltonian: spin index out of range");
+            assert_ne!(i, j, "SparseCouplingHamiltonian: self-couplings are not allowed");
+            let m = c.to_matrix();
+            pairs.push(if i < j { (i, j, m) } else { (j, i, m.transpose()) });
+        }
+        pairs.sort_by_key(|&(i, j, _)| (i, j));
+        let mut merged : Vec<(usize, usize, Matrix3<f64>)> = Vec::with_capacity(pairs.len());
+        for (i, j, m) in pairs{
+            match merged.last_mut(){
+                Some(last) if last.0 == i && last.1 == j => last.2 += m,
+                _ => merged.push((i, j, m))
+            }
+        }
+
+        // CSR rows with J_{ji} = J_{ij}^T, sorted by column
+        let mut rows : Vec<Vec<(usize, Matrix3<f64>)>> = vec![Vec::new(); n];
+        for &(i, j, m) in merged.iter(){
+            rows[i].push((j, m));
+            rows[j].push((i, m.transpose()));
+        }
+        rows.iter_mut().for_each(|row| row.sort_by_key(|&(j, _)| j));
+        let row_len = rows.iter().map(|row| row.len()).collect();
+
+        let mut chunk_fields = vec![Vector3d4xf64::zero(); n_ch];
+        for (i, h) in fields.iter().enumerate(){
+            for a in 0..3{
+                chunk_fields[i / 4][a].dat[i % 4] = h[a];
+            }
+        }
+        // Slot k of a chunk holds the k-th entry of each of the four rows of the chunk
+        let mut chunk_ptr = Vec::with_capacity(n_ch + 1);
+        let mut slot_idx = Vec::new();
+        let mut slot_mats : Vec<[Matrix3<f64>; 4]> = Vec::new();
+        chunk_ptr.push(0);
+        for c in 0..n_ch{
+            let lanes = 4 * c..(4 * c + 4).min(n);
+            let degree = lanes.clone().map(|i| rows[i].len()).max().unwrap_or(0);
+            for k in 0..degree{
+                // Padded slots point at spin 0 with a vanishing coupling
+                let mut idx = [0; 4];
+                let mut mats = [Matrix3::zeros(); 4];
+                for (l, i) in lanes.clone().enumerate(){
+                    if let Some(&(j, m)) = rows[i].get(k){
+                        idx[l] = j;
+                        mats[l] = m;
+                    }
+                }
+                slot_idx.push(idx);
+                slot_mats.push(mats);
+            }
+            chunk_ptr.push(slot_idx.len());
+        }
+
+        let pack = |a: usize, b: usize, mats: &[Matrix3<f64>; 4]| -> Aligned4xf64{
+            let mut x = Aligned4xf64::from(0.0);
+            for (l, m) in mats.iter().enumerate(){
+                x.dat[l] = m[(a, b)];
+            }
+            x
+        };
+        let values = match kind{
+            0 => CouplingValues::Ising(slot_mats.iter().map(|mats| pack(2, 2, mats)).collect()),
+            1 => CouplingValues::Xyz(slot_mats.iter().map(|mats|
+                Vector3d4xf64::new(pack(0, 0, mats), pack(1, 1, mats), pack(2, 2, mats))).collect()),
+            _ => CouplingValues::Anisotropic(slot_mats.iter().map(|mats|{
+                let mut jm = Matrix3d4xf64::zero();
+                for a in 0..3{
+                    for b in 0..3{
+                        jm[(a, b)] = pack(a, b, mats);
+                    }
+                }
+                jm
+            }).collect())
+        };
+
+        Self{n, chunk_fields, row_len, chunk_ptr, slot_idx, values}
+    }
+
+    pub fn num_spins(&self) -> usize{
+        self.n
+    }
+
+    /// Number of 4xf64 chunks in a row of spins
+    pub fn num_chunks(&self) -> usize{
+        self.chunk_fields.len()
+    }
+
+    /// Number of coupled pairs (i, j)
+    pub fn num_couplings(&self) -> usize{
+        self.row_len.iter().sum::<usize>() / 2
+    }
+
+    /// The local fields h_i, unpacked from the chunks
+    pub fn fields(&self) -> Vec<Vector3<f64>>{
+        (0..self.n).map(|i|{
+            let hc = &self.chunk_fields[i / 4];
+            Vector3::new(hc[0].dat[i % 4], hc[1].dat[i % 4], hc[2].dat[i % 4])
+        }).collect()
+    }
+
+    /// Coupling tensor of lane l of slot k
+    fn tensor(&self, k: usize, l: usize) -> CouplingTensor{
+        match &self.values{
+            CouplingValues::Ising(v) => CouplingTensor::Ising(v[k].dat[l]),
+            CouplingValues::Xyz(v) => CouplingTensor::Xyz(Vector3::new(v[k][0].dat[l], v[k][1].dat[l], v[k][2].dat[l])),
+            CouplingValues::Anisotropic(v) => CouplingTensor::Anisotropic(Matrix3::from_fn(|a, b| v[k][(a, b)].dat[l]))
+        }
+    }
+
+    /// The couplings (i, j, J_{ij}) with i < j, in the order of the CSR rows
+    pub fn couplings(&self) -> Vec<(usize, usize, CouplingTensor)>{
+        let mut c = Vec::with_capacity(self.num_couplings());
+        for i in 0..self.n{
+            let (first, l) = (self.chunk_ptr[i / 4], i % 4);
+            for k in first..first + self.row_len[i]{
+                let j = self.slot_idx[k][l];
+                if j > i{
+                    c.push((i, j, self.tensor(k, l)));
+                }
+            }
+        }
+        c
+    }
+
+    /// Multiply the local fields by `h_scale` and the couplings by `j_scale`
+    pub fn scale(&mut self, h_scale: f64, j_scale: f64){
+        let hs = Aligned4xf64::from(h_scale);
+        let js = Aligned4xf64::from(j_scale);
+        self.chunk_fields.iter_mut().for_each(|h| *h *= hs);
+        match &mut self.values{
+            CouplingValues::Ising(v) => v.iter_mut().for_each(|j| *j *= js),
+            CouplingValues::Xyz(v) => v.iter_mut().for_each(|j| *j *= js),
+            CouplingValues::Anisotropic(v) => v.iter_mut().for_each(|j| *j *= js),
+        }
+    }
+
+    /// Add the effective fields  -h_i - \sum_j J_{ij} m_j  to `h`.
+    /// The spins are packed in chunks of four as by `xyz_to_array_chunks`.
+    pub fn add_local_fields(&self, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        assert_eq!(m.len(), self.num_chunks(), "SparseCouplingHamiltonian: mismatching number of chunks");
+        assert_eq!(h.len(), self.num_chunks(), "SparseCouplingHamiltonian: mismatching number of chunks");
+        for (c, hc) in h.iter_mut().enumerate(){
+            let mut acc = -self.chunk_fields[c];
+            let slots = self.chunk_ptr[c]..self.chunk_ptr[c+1];
+            match &self.values{
+                CouplingValues::Ising(v) => {
+                    for k in slots{
+                        let mj = gather(m, &self.slot_idx[k]);
+                        acc[2] -= v[k] * mj[2];
+                    }
+                },
+                CouplingValues::Xyz(v) => {
+                    for k in slots{
+                        let mj = gather(m, &self.slot_idx[k]);
+                        for a in 0..3{
+                            acc[a] -= v[k][a] * mj[a];
+                        }
+                    }
+                },
+                CouplingValues::Anisotropic(v) => {
+                    for k in slots{
+                        let mj = gather(m, &self.slot_idx[k]);
+                        let jk = &v[k];
+                        for a in 0..3{
+                            for b in 0..3{
+                                acc[a] -= jk[(a, b)] * mj[b];
+                            }
+                        }
+                    }
+                }
+            }
+            *hc += acc;
+        }
+    }
+
+    /// Evaluate the local fields of a row of spins. The fields are overwritten, so this can be
+    /// used directly as the Hamiltonian closure of `spin_langevin_step`.
+    pub fn local_fields(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        h.fill(Zero::zero());
+        self.add_local_fields(m, h);
+    }
+
+    /// The Hamiltonian closure of `spin_langevin_step`
+    pub fn haml_fn(&self) -> impl Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync + '_{
+        move |t, m, h| self.local_fields(t, m, h)
+    }
+
+    /// Energy of a row of spins
+    pub fn energy(&self, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        let mut f : ndarray::Array1<Vector3d4xf64> = ndarray::Array1::from_elem(m.len(), Zero::zero());
+        // With the effective fields F_i = -h_i - \sum_j J_ij m_j,
+        //  E = \sum_i m_i . (h_i - F_i) / 2
+        self.add_local_fields(m, &mut f.view_mut());
+        let mut e = Aligned4xf64::from(0.0);
+        for ((mc, fc), hc) in m.iter().zip(f.iter()).zip(self.chunk_fields.iter()){
+            let v = (hc - fc) * Aligned4xf64::from(0.5);
+            e += mc[0] * v[0] + mc[1] * v[1] + mc[2] * v[2];
+        }
+        4.0 * e.mean_reduce()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use nalgebra::{Matrix3, Vector3};
+    use ndarray::{Array1, Array2};
+    use num_traits::Zero;
+    use simd_phys::r3::Vector3d4xf64;
+
+    use super::{CouplingTensor, SparseCouplingHamiltonian};
+    use crate::xyz_to_array_chunks;
+
+    #[test]
+    fn test_sparse_local_fields(){
+        // Five spins in a chain, spanning two chunks, with a repeated coupling
+        let ham = SparseCouplingHamiltonian::ising(&[0.5, 0.0, -0.5, 0.0, 0.25],
+                                                   &[(0, 1, 1.0), (1, 2, -1.0), (1, 0, 0.5), (3, 4, 2.0)]);
+        assert_eq!(ham.num_couplings(), 3);
+        assert_eq!(ham.num_chunks(), 2);
+        let sz = [1.0, -1.0, 1.0, 1.0, -1.0];
+        let mut xyz = Array2::zeros((5, 3));
+        for (i, &s) in sz.iter().enumerate(){
+            xyz[(i, 2)] = s;
+        }
+        let mut m : Array1<Vector3d4xf64> = Array1::from_elem(2, Zero::zero());
+        xyz_to_array_chunks(xyz.view(), m.view_mut());
+
+        let mut h = Array1::from_elem(2, Zero::zero());
+        ham.local_fields(0.0, &m.view(), &mut h.view_mut());
+        assert_eq!(h[0][2].dat, [1.0, -0.5, -0.5, 2.0]);
+        assert_eq!(h[1][2].dat, [-2.25, 0.0, 0.0, 0.0]);
+        assert_eq!(h[0][0].dat, [0.0; 4]);
+        // E = 0.5 - 0.5 - 0.25 + 1.5 * (-1) - 1 * (-1) + 2 * (-1)
+        assert!((ham.energy(&m.view()) + 2.75).abs() < 1.0e-12);
+        assert_eq!(ham.couplings(), vec![(0, 1, CouplingTensor::Ising(1.5)), (1, 2, CouplingTensor::Ising(-1.0)),
+                                         (3, 4, CouplingTensor::Ising(2.0))]);
+        let mut scaled = ham.clone();
+        scaled.scale(2.0, -1.0);
+        assert_eq!(scaled.fields()[4], Vector3::new(0.0, 0.0, 0.5));
+        assert!((scaled.energy(&m.view()) - 2.0).abs() < 1.0e-12);
+
+        // The same Hamiltonian through the general tensors
+        let mut jzz = Matrix3::zeros();
+        jzz[(2, 2)] = 1.5;
+        let mut fields = vec![Vector3::zeros(); 5];
+        fields[0][2] = 0.5;
+        fields[2][2] = -0.5;
+        fields[4][2] = 0.25;
+        let ham2 = SparseCouplingHamiltonian::anisotropic(
+            &fields, &[(0, 1, jzz), (1, 2, -jzz / 1.5), (4, 3, jzz * 4.0 / 3.0)]);
+        let mut h2 = Array1::from_elem(2, Zero::zero());
+        ham2.local_fields(0.0, &m.view(), &mut h2.view_mut());
+        assert_eq!(h, h2);
+        let ham3 = SparseCouplingHamiltonian::from_tensors(
+            &fields, &[(0, 1, CouplingTensor::Xyz(Vector3::new(0.0, 0.0, 1.5))),
+                       (1, 2, CouplingTensor::Ising(-1.0)),
+                       (3, 4, CouplingTensor::Ising(2.0))]);
+        let mut h3 = Array1::from_elem(2, Zero::zero());
+        ham3.local_fields(0.0, &m.view(), &mut h3.view_mut());
+        assert_eq!(h, h3);
+    }
+}
@@ -0,0 +1,327 @@
+//! Composable energy terms of magnetic Hamiltonians
+//!
+//! Each `FieldTerm` adds its contribution to the local fields of a row of spins, with the sign
+//! convention h_i = -\partial E / \partial m_i. The terms of this module, for n spins, are
+//!     Zeeman                  E = -\sum_i B(t) \cdot m_i
+//!     Uniaxial anisotropy     E = -\sum_i K_i (m_i \cdot u)^2
+//!     Cubic anisotropy        E = K_c \sum_i (m_x^2 m_y^2 + m_y^2 m_z^2 + m_z^2 m_x^2)_i
+//!     Dzyaloshinskii-Moriya   E = \sum_{(i, j)} D_{ij} \cdot (m_i \cross m_j)
+//! and `SparseCouplingHamiltonian` provides the exchange couplings. Padding lanes are excluded from
+//! the energies and receive no fields.
+//!
+//! `CompositeHamiltonian` sums any number of terms. It resets the fields before summing, so it can
+//! be used directly as the Hamiltonian closure of `spin_langevin_step`.
+
+use nalgebra::Vector3;
+use ndarray::{ArrayView1, ArrayViewMut1};
+use num_traits::Zero;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use super::SparseCouplingHamiltonian;
+use crate::bath::Coefficient;
+use crate::{num_chunks, pack_lanes};
+
+/// A contribution to the energy and the local fields of a row of spins
+pub trait FieldTerm : Send + Sync{
+    /// Add the fields -\partial E / \partial m of the term at time t to `h`
+    fn add_fields(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>);
+
+    /// Energy of the term for a row of spins at time t
+    fn energy(&self, t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64;
+}
+
+impl FieldTerm for SparseCouplingHamiltonian{
+    fn add_fields(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        self.add_local_fields(m, h);
+    }
+
+    fn energy(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        SparseCouplingHamiltonian::energy(self, m)
+    }
+}
+
+#[inline]
+fn splat(v: &Vector3<f64>) -> Vector3d4xf64{
+    Vector3d4xf64::new(Aligned4xf64::from(v[0]), Aligned4xf64::from(v[1]), Aligned4xf64::from(v[2]))
+}
+
+#[inline]
+fn dot(a: &Vector3d4xf64, b: &Vector3d4xf64) -> Aligned4xf64{
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[inline]
+fn lane_sum(x: Aligned4xf64) -> f64{
+    x.dat.iter().sum()
+}
+
+/// Uniform field B(t) = a(t) b on n spins, with the fixed vector b = `direction` and the possibly
+/// time-dependent amplitude a(t)
+#[derive(Clone, Debug)]
+pub struct Zeeman<C=f64>{
+    pub direction: Vector3<f64>,
+    pub amplitude: C,
+    mask: Vec<Aligned4xf64>
+}
+
+impl<C: Coefficient + Send> Zeeman<C>{
+    pub fn new(n: usize, direction: Vector3<f64>, amplitude: C) -> Self{
+        Self{direction, amplitude, mask: pack_lanes(&vec![1.0; n])}
+    }
+}
+
+impl<C: Coefficient + Send> FieldTerm for Zeeman<C>{
+    fn add_fields(&self, t: f64, _m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        let b = splat(&(self.direction * self.amplitude.at(t)));
+        for (hc, &mask) in h.iter_mut().zip(self.mask.iter()){
+            *hc += b * mask;
+        }
+    }
+
+    fn energy(&self, t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        let b = splat(&(self.direction * self.amplitude.at(t)));
+        -m.iter().zip(self.mask.iter()).map(|(mc, &mask)| lane_sum(dot(mc, &b) * mask)).sum::<f64>()
+    }
+}
+
+/// Uniaxial anisotropy with the easy axis u and the constants K_i (K_i < 0 for an easy plane)
+#[derive(Clone, Debug)]
+pub struct UniaxialAnisotropy{
+    pub axis: Vector3<f64>,
+    k: Vec<Aligned4xf64>
+}
+
+impl UniaxialAnisotropy{
+    /// Anisotropy K along `axis` (normalized) on n spins
+    pub fn new(n: usize, k: f64, axis: Vector3<f64>) -> Self{
+        Self::spinwise(&vec![k; n], axis)
+    }
+
+    /// Anisotropy K_i of each spin along `axis` (normalized)
+    pub fn spinwise(k: &[f64], axis: Vector3<f64>) -> Self{
+        let norm = axis.norm();
+        assert!(norm > 0.0, "UniaxialAnisotropy: zero axis");
+        Self{axis: axis / norm, k: pack_lanes(k)}
+    }
+}
+
+impl FieldTerm for UniaxialAnisotropy{
+    fn add_fields(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        let u = splat(&self.axis);
+        let two = Aligned4xf64::from(2.0);
+        for ((hc, mc), &k) in h.iter_mut().zip(m.iter()).zip(self.k.iter()){
+            *hc += u * (dot(mc, &u) * k * two);
+        }
+    }
+
+    fn energy(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        let u = splat(&self.axis);
+        -m.iter().zip(self.k.iter()).map(|(mc, &k)|{
+            let mu = dot(mc, &u);
+            lane_sum(mu * mu * k)
+        }).sum::<f64>()
+    }
+}
+
+/// Cubic anisotropy with the constant K_c along the coordinate axes
+/// (K_c > 0 for <100> easy axes, K_c < 0 for <111> easy axes)
+#[derive(Clone, Debug)]
+pub struct CubicAnisotropy{
+    k: Vec<Aligned4xf64>
+}
+
+impl CubicAnisotropy{
+    pub fn new(n: usize, k_c: f64) -> Self{
+        Self{k: pack_lanes(&vec![k_c; n])}
+    }
+}
+
+impl FieldTerm for CubicAnisotropy{
+    fn add_fields(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        let two = Aligned4xf64::from(2.0);
+        for ((hc, mc), &k) in h.iter_mut().zip(m.iter()).zip(self.k.iter()){
+            let sq = [mc[0] * mc[0], mc[1] * mc[1], mc[2] * mc[2]];
+            for a in 0..3{
+                hc[a] -= mc[a] * (sq[(a + 1) % 3] + sq[(a + 2) % 3]) * k * two;
+            }
+        }
+    }
+
+    fn energy(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        m.iter().zip(self.k.iter()).map(|(mc, &k)|{
+            let sq = [mc[0] * mc[0], mc[1] * mc[1], mc[2] * mc[2]];
+            lane_sum((sq[0] * sq[1] + sq[1] * sq[2] + sq[2] * sq[0]) * k)
+        }).sum::<f64>()
+    }
+}
+
+/// Dzyaloshinskii-Moriya interaction with the vectors D_{ij} on the bonds (i, j)
+#[derive(Clone, Debug)]
+pub struct DmiTerm{
+    /// Neighbors j of each spin i with the vector D'_{ij} such that h_i = \sum_j D'_{ij} \cross m_j
+    neighbors: Vec<Vec<(usize, Vector3<f64>)>>,
+    bonds: Vec<(usize, usize, Vector3<f64>)>
+}
+
+impl DmiTerm{
+    /// DMI on n spins with the bonds (i, j, D_{ij}), each listed once
+    pub fn new(n: usize, bonds: Vec<(usize, usize, Vector3<f64>)>) -> Self{
+        let mut neighbors = vec![Vec::new(); n];
+        for &(i, j, d) in bonds.iter(){
+            assert!(i < n && j < n && i != j, "DmiTerm: invalid bond indices");
+            // E_{ij} = m_i \cdot (m_j \cross D) = m_j \cdot (D \cross m_i)
+            neighbors[i].push((j, d));
+            neighbors[j].push((i, -d));
+        }
+        Self{neighbors, bonds}
+    }
+
+    /// Interfacial DMI  D_{ij} = d (\hat{r}_{ij} \cross \hat{n})  for the bond directions r_{ij} and the
+    /// interface normal n
+    pub fn interfacial(n: usize, bonds: &[(usize, usize, Vector3<f64>)], d: f64, normal: Vector3<f64>) -> Self{
+        let normal = normal.normalize();
+        Self::new(n, bonds.iter().map(|&(i, j, r)| (i, j, r.normalize().cross(&normal) * d)).collect())
+    }
+
+    /// Bulk DMI  D_{ij} = d \hat{r}_{ij}  for the bond directions r_{ij}
+    pub fn bulk(n: usize, bonds: &[(usize, usize, Vector3<f64>)], d: f64) -> Self{
+        Self::new(n, bonds.iter().map(|&(i, j, r)| (i, j, r.normalize() * d)).collect())
+    }
+
+    pub fn bonds(&self) -> &[(usize, usize, Vector3<f64>)]{
+        &self.bonds
+    }
+}
+
+impl FieldTerm for DmiTerm{
+    fn add_fields(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        assert!(m.len() >= num_chunks(self.neighbors.len()), "DmiTerm: not enough chunks");
+        for (i, nb) in self.neighbors.iter().enumerate(){
+            // h_i = -\partial E / \partial m_i = \sum_j D_{ij} \cross m_j
+            let mut hi = Vector3::zeros();
+            for &(j, d) in nb.iter(){
+                let mj = Vector3::new(m[j / 4][0].dat[j % 4], m[j / 4][1].dat[j % 4], m[j / 4][2].dat[j % 4]);
+                hi += d.cross(&mj);
+            }
+            for a in 0..3{
+                h[i / 4][a].dat[i % 4] += hi[a];
+            }
+        }
+    }
+
+    fn energy(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        let spin = |i: usize| Vector3::new(m[i / 4][0].dat[i % 4], m[i / 4][1].dat[i % 4], m[i / 4][2].dat[i % 4]);
+        self.bonds.iter().map(|&(i, j, d)| d.dot(&spin(i).cross(&spin(j)))).sum()
+    }
+}
+
+/// Sum of field terms
+#[derive(Default)]
+pub struct CompositeHamiltonian{
+    terms: Vec<Box<dyn FieldTerm>>
+}
+
+impl CompositeHamiltonian{
+    pub fn new() -> Self{
+        Self{terms: Vec::new()}
+    }
+
+    /// Add a term to the Hamiltonian
+    pub fn with<T: FieldTerm + 'static>(mut self, term: T) -> Self{
+        self.push(term);
+        self
+    }
+
+    pub fn push<T: FieldTerm + 'static>(&mut self, term: T){
+        self.terms.push(Box::new(term));
+    }
+
+    pub fn num_terms(&self) -> usize{
+        self.terms.len()
+    }
+
+    /// Evaluate the local fields of a row of spins. The fields are overwritten.
+    pub fn local_fields(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        h.fill(Zero::zero());
+        for term in self.terms.iter(){
+            term.add_fields(t, m, h);
+        }
+    }
+
+    /// The Hamiltonian closure of `spin_langevin_step`
+    pub fn haml_fn(&self) -> impl Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync + '_{
+        move |t, m, h| self.local_fields(t, m, h)
+    }
+}
+
+impl FieldTerm for CompositeHamiltonian{
+    fn add_fields(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        for term in self.terms.iter(){
+            term.add_fields(t, m, h);
+        }
+    }
+
+    fn energy(&self, t: f64, m: &ArrayView1<Vector3d4xf64>) -> f64{
+        self.terms.iter().map(|term| term.energy(t, m)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use nalgebra::Vector3;
+    use ndarray::Array1;
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{CompositeHamiltonian, CubicAnisotropy, DmiTerm, FieldTerm, UniaxialAnisotropy, Zeeman};
+    use crate::hamiltonian::SparseCouplingHamiltonian;
+
+    #[test]
+    fn test_field_terms_gradient(){
+        let n = 6;
+        let bonds : Vec<(usize, usize, Vector3<f64>)> = (0..n - 1).map(|i| (i, i + 1, Vector3::x())).collect();
+        let ham = CompositeHamiltonian::new()
+            .with(Zeeman::new(n, Vector3::new(0.0, 0.6, 0.8), |t: f64| 0.5 * (2.0 * t).cos()))
+            .with(UniaxialAnisotropy::new(n, 0.7, Vector3::new(1.0, 1.0, 0.0)))
+            .with(CubicAnisotropy::new(n, -0.3))
+            .with(DmiTerm::interfacial(n, &bonds, 0.4, Vector3::z()))
+            .with(SparseCouplingHamiltonian::heisenberg(&[Vector3::new(0.1, 0.0, -0.2); 6],
+                &[(0, 1, Vector3::new(-1.0, -1.0, -1.0)), (2, 5, Vector3::new(0.5, 0.2, 0.1))]));
+        assert_eq!(ham.num_terms(), 5);
+
+        let mut rng = Xoshiro256Plus::seed_from_u64(4);
+        let mut m = Array1::from_elem(2, Vector3d4xf64::zeros());
+        for i in 0..n{
+            for a in 0..3{
+                m[i / 4][a].dat[i % 4] = rng.gen_range(-1.0, 1.0);
+            }
+        }
+        // Stale values are overwritten
+        let mut h = Array1::from_elem(2, Vector3d4xf64::new(
+            Aligned4xf64::from(9.0), Aligned4xf64::from(9.0), Aligned4xf64::from(9.0)));
+        let t = 0.3;
+        ham.local_fields(t, &m.view(), &mut h.view_mut());
+
+        // h = -\partial E / \partial m by central differences
+        let eps = 1.0e-6;
+        for i in 0..8{
+            for a in 0..3{
+                let mut mp = m.clone();
+                let mut mm = m.clone();
+                mp[i / 4][a].dat[i % 4] += eps;
+                mm[i / 4][a].dat[i % 4] -= eps;
+                let grad = (ham.energy(t, &mp.view()) - ham.energy(t, &mm.view())) / (2.0 * eps);
+                let hi = h[i / 4][a].dat[i % 4];
+                if i < n{
+                    assert!((hi + grad).abs() < 1.0e-6, "spin {} axis {}: {} != {}", i, a, hi, -grad);
+                } else {
+                    assert_eq!(hi, 0.0);
+                    assert!(grad.abs() < 1.0e-9);
+                }
+            }
+        }
+    }
+}
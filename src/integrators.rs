@@ -0,0 +1,472 @@
+//! Alternative integration schemes of the Spin-Langevin equation
+//!      \dd M   =  ( H(M) - \eta H(M) \cross M ) \cross M  \dd t + \sqrt(b) \dd xi(t) \cross M
+//! implementing `SpinIntegrator`, so that they can be driven by `spin_langevin_step_scheme`,
+//! `spin_langevin_step_noise` or `SpinLangevinIntegrator` in place of the Magnus propagators.
+//! All schemes are consistent with the Stratonovich form. `ItoEulerMaruyama` integrates the
+//! equivalent Ito form with an explicit drift correction.
+//!
+//! Below, g = H - \eta H \cross M is the effective field evaluated by `h_update_row`, and
+//! \Delta W = \sqrt{\delta_t/2} (\chi_1 + \chi_2) is the Brownian increment of the step.
+//!
+//! References:
+//! 1.  Depondt, P. & Mertens, F. G. Spin dynamics simulations of two-dimensional clusters with
+//!     Heisenberg and dipole-dipole interactions. J. Phys.: Condens. Matter 21, 336005 (2009).
+//! 2.  Mentink, J. H., Tretyakov, M. V., Fasolino, A., Katsnelson, M. I. & Rasing, T. Stable and
+//!     fast semi-implicit integration of the stochastic Landau-Lifshitz equation.
+//!     J. Phys.: Condens. Matter 22, 176001 (2010).
+
+use std::sync::Mutex;
+
+use ndarray::{Array2, ArrayView1, ArrayViewMut1, Zip};
+use rand::Rng;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::{h_update_row, m_update_row, spin_langevin_step_scheme, SpinIntegrator, SpinLangevinRowWorkpad};
+
+#[inline]
+fn norm_sq(v: &Vector3d4xf64) -> Aligned4xf64{
+    v[0]*v[0] + v[1]*v[1] + v[2]*v[2]
+}
+
+/// Rescale m to have the squared norm `target_sq`
+#[inline]
+fn renormalize(m: &mut Vector3d4xf64, target_sq: Aligned4xf64){
+    let inv_sq = norm_sq(m).map(|x| 1.0 / x);
+    *m *= (target_sq * inv_sq).map(f64::sqrt);
+}
+
+/// Norm preserving Cayley transform. Evaluates the solution m_f of
+///     m_f = m_0 + a \cross (m_0 + m_f) / 2
+/// which is
+///     m_f = [ (1 - |a|^2/4) m_0 + a \cross m_0 + (a . m_0) a / 2 ] / (1 + |a|^2/4)
+#[inline]
+fn cayley_update(a: &Vector3d4xf64, m0: &Vector3d4xf64, mf: &mut Vector3d4xf64){
+    let q = norm_sq(a) * Aligned4xf64::from(0.25);
+    let inv_denom = (q + Aligned4xf64::from(1.0)).map(|x| 1.0 / x);
+    let a_dot_m = a[0]*m0[0] + a[1]*m0[1] + a[2]*m0[2];
+    *mf = (m0 * (Aligned4xf64::from(1.0) - q) + a.cross(m0) + a * (a_dot_m * Aligned4xf64::from(0.5)))
+        * inv_denom;
+}
+
+#[inline]
+fn cayley_update_row(a: &ArrayView1<Vector3d4xf64>, m0: &ArrayView1<Vector3d4xf64>,
+                     mf: &mut ArrayViewMut1<Vector3d4xf64>){
+    Zip::from(a).and(m0).and(mf)
+        .apply(|a, m0, mf|{
+            cayley_update(a, m0, mf);
+        });
+}
+
+/// Stochastic Heun scheme (Stratonovich-consistent predictor-corrector):
+///     \tilde{m} = m_0 + (\delta_t g(t_0, m_0) + \Delta W) \cross m_0
+///     m_f = m_0 + 1/2 [ (\delta_t g(t_0, m_0) + \Delta W) \cross m_0
+///                     + (\delta_t g(t_0 + \delta_t, \tilde{m}) + \Delta W) \cross \tilde{m} ]
+/// followed by a rescaling of m_f to the norm of m_0.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StochasticHeun;
+
+impl SpinIntegrator for StochasticHeun{
+    fn order(&self) -> u32{
+        2
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mut mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, h1, omega1, omega2, chi1, chi2, ..} = work;
+        let dt = Aligned4xf64::from(delta_t);
+        let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+        let half = Aligned4xf64::from(0.5);
+
+        // Predictor
+        h_update_row(t0, eta, haml_fn, &mut h0.view_mut(), &m0);
+        Zip::from(&m0).and(&*h0).and(&mut *omega1).and(&mut mf).and(&*chi1).and(&*chi2)
+            .apply(|m0, h0, o1, mf, chi1, chi2|{
+                *o1 = h0 * dt + (chi1 + chi2) * half_sqrt;
+                *mf = m0 + o1.cross(m0);
+            });
+        // Corrector
+        h_update_row(t0 + delta_t, eta, haml_fn, &mut h1.view_mut(), &mf.view());
+        Zip::from(&m0).and(&*h0).and(&*h1).and(&mut *omega2).and(&mut mf).and(&*omega1)
+            .apply(|m0, h0, h1, o2, mf, o1|{
+                let dw = o1 - h0 * dt;
+                let o_pred = h1 * dt + dw;
+                *o2 = (h0 + h1) * (dt * half) + dw;
+                let mut m = m0 + (o1.cross(m0) + o_pred.cross(mf)) * half;
+                renormalize(&mut m, norm_sq(m0));
+                *mf = m;
+            });
+    }
+}
+
+/// Depondt-Mertens scheme: the Heun predictor-corrector applied to the rotation generators
+///     \tilde{m} = \exp{\delta_t g(t_0, m_0) + \Delta W} m_0
+///     m_f = \exp{ (\delta_t/2) [ g(t_0, m_0) + g(t_0 + \delta_t, \tilde{m}) ] + \Delta W } m_0
+/// The update is an exact rotation and preserves the spin norms.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DepondtMertens;
+
+impl SpinIntegrator for DepondtMertens{
+    fn order(&self) -> u32{
+        2
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mut mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, h1, omega1, omega2, chi1, chi2, ..} = work;
+        let dt = Aligned4xf64::from(delta_t);
+        let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+
+        h_update_row(t0, eta, haml_fn, &mut h0.view_mut(), &m0);
+        Zip::from(&*h0).and(&mut *omega1).and(&*chi1).and(&*chi2)
+            .apply(|h0, o1, chi1, chi2|{
+                *o1 = h0 * dt + (chi1 + chi2) * half_sqrt;
+            });
+        m_update_row(&omega1.view(), &m0, &mut mf);
+
+        h_update_row(t0 + delta_t, eta, haml_fn, &mut h1.view_mut(), &mf.view());
+        Zip::from(&*h0).and(&*h1).and(&mut *omega2).and(&*chi1).and(&*chi2)
+            .apply(|h0, h1, o2, chi1, chi2|{
+                *o2 = (h0 + h1) * (dt / 2.0) + (chi1 + chi2) * half_sqrt;
+            });
+        m_update_row(&omega2.view(), &m0, &mut mf);
+    }
+}
+
+/// Semi-implicit scheme B (SIB) of Mentink et al. Each stage solves the implicit midpoint
+/// equation with a frozen field, which is done exactly by the Cayley transform:
+///     \tilde{m} = m_0 + (\delta_t g(t_0, m_0) + \Delta W) \cross (m_0 + \tilde{m}) / 2
+///     m_f = m_0 + (\delta_t g(t_0 + \delta_t/2, (m_0 + \tilde{m})/2) + \Delta W) \cross (m_0 + m_f) / 2
+/// The update preserves the spin norms.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SemiImplicitB;
+
+impl SpinIntegrator for SemiImplicitB{
+    fn order(&self) -> u32{
+        2
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mut mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, h1, h2, omega1, omega2, chi1, chi2, ..} = work;
+        let dt = Aligned4xf64::from(delta_t);
+        let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+        let half = Aligned4xf64::from(0.5);
+
+        // Predictor
+        h_update_row(t0, eta, haml_fn, &mut h0.view_mut(), &m0);
+        Zip::from(&*h0).and(&mut *omega1).and(&*chi1).and(&*chi2)
+            .apply(|h0, o1, chi1, chi2|{
+                *o1 = h0 * dt + (chi1 + chi2) * half_sqrt;
+            });
+        cayley_update_row(&omega1.view(), &m0, &mut mf);
+
+        // Corrector at the midpoint, stored in h2
+        Zip::from(&mut *h2).and(&m0).and(&mf)
+            .apply(|m_mid, m0, mf|{
+                *m_mid = (m0 + mf) * half;
+            });
+        h_update_row(t0 + delta_t / 2.0, eta, haml_fn, &mut h1.view_mut(), &h2.view());
+        Zip::from(&*h1).and(&mut *omega2).and(&*chi1).and(&*chi2)
+            .apply(|h1, o2, chi1, chi2|{
+                *o2 = h1 * dt + (chi1 + chi2) * half_sqrt;
+            });
+        cayley_update_row(&omega2.view(), &m0, &mut mf);
+    }
+}
+
+/// Implicit midpoint rule
+///     m_f = m_0 + (\delta_t g(t_0 + \delta_t/2, (m_0 + m_f)/2) + \Delta W) \cross (m_0 + m_f) / 2
+/// solved by fixed point iteration on the midpoint field, with each iterate evaluated exactly
+/// by the Cayley transform. The iteration starts from the frozen field g(t_0, m_0) and stops once
+/// the largest change of a spin component falls below `tol`, or after `max_iter` iterations.
+/// The update preserves the spin norms.
+#[derive(Copy, Clone, Debug)]
+pub struct ImplicitMidpoint{
+    pub max_iter: usize,
+    pub tol: f64
+}
+
+impl Default for ImplicitMidpoint{
+    fn default() -> Self {
+        ImplicitMidpoint{max_iter: 20, tol: 1.0e-12}
+    }
+}
+
+impl SpinIntegrator for ImplicitMidpoint{
+    fn order(&self) -> u32{
+        2
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mut mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, h1, h2, omega1, omega2, chi1, chi2, ..} = work;
+        let dt = Aligned4xf64::from(delta_t);
+        let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+        let half = Aligned4xf64::from(0.5);
+        let t_mid = t0 + delta_t / 2.0;
+
+        h_update_row(t0, eta, haml_fn, &mut h0.view_mut(), &m0);
+        Zip::from(&*h0).and(&mut *omega2).and(&*chi1).and(&*chi2)
+            .apply(|h0, o2, chi1, chi2|{
+                *o2 = h0 * dt + (chi1 + chi2) * half_sqrt;
+            });
+        cayley_update_row(&omega2.view(), &m0, &mut mf);
+
+        for _ in 0..self.max_iter{
+            omega1.assign(&*omega2);
+            // Midpoint spins in h2, and the previous iterate in h1
+            h1.assign(&mf);
+            Zip::from(&mut *h2).and(&m0).and(&mf)
+                .apply(|m_mid, m0, mf|{
+                    *m_mid = (m0 + mf) * half;
+                });
+            h_update_row(t_mid, eta, haml_fn, &mut h0.view_mut(), &h2.view());
+            Zip::from(&*h0).and(&mut *omega2).and(&*chi1).and(&*chi2)
+                .apply(|h0, o2, chi1, chi2|{
+                    *o2 = h0 * dt + (chi1 + chi2) * half_sqrt;
+                });
+            cayley_update_row(&omega2.view(), &m0, &mut mf);
+
+            let max_diff = mf.iter().zip(h1.iter())
+                .map(|(m, m_prev)|{
+                    let d = m - m_prev;
+                    d.iter().flat_map(|x| x.dat.iter()).fold(0.0_f64, |acc, x| acc.max(x.abs()))
+                })
+                .fold(0.0_f64, f64::max);
+            if max_diff < self.tol{
+                break;
+            }
+        }
+    }
+}
+
+/// Euler-Maruyama scheme for the Ito form of the Spin-Langevin equation.
+///
+/// The Stratonovich equation is equivalent to the Ito equation
+///     \dd M = ( g \cross M - 2 D M ) \dd t + \sqrt(b) \dd W \cross M
+/// where the noise-induced drift  -2 D M = -b M  follows from
+///     (b/2) \sum_k e_k \cross (e_k \cross M) = -b M
+/// for isotropic noise with unit variance per component. One step is
+///     m_f = m_0 + (\delta_t g(t_0, m_0) + \Delta W) \cross m_0 - b \delta_t m_0
+/// followed by a rescaling of m_f to the norm of m_0. The drift correction keeps the mean
+/// squared norm of the spins constant before the rescaling.
+///
+/// Setting `drift_correction` to false integrates the Ito interpretation of the equation
+/// as written, which differs from the Stratonovich form at O(\eta k_b T \delta_t),
+/// and setting `renormalize` to false leaves the spin norms free.
+///
+/// The noise strength b of the drift correction is taken from `work.b`, which the drivers set to
+/// the noise strength of the step. For anisotropic or spin dependent baths, `SpinBath::mean_b`
+/// is the isotropic average of the noise, and the correction is only exact in the mean.
+/// The scheme has no embedded error estimate: the same generator
+/// \delta_t g(t_0, m_0) + \Delta W is stored in `omega1` and `omega2`.
+#[derive(Copy, Clone, Debug)]
+pub struct ItoEulerMaruyama{
+    pub drift_correction: bool,
+    pub renormalize: bool
+}
+
+impl Default for ItoEulerMaruyama{
+    fn default() -> Self{
+        ItoEulerMaruyama{drift_correction: true, renormalize: true}
+    }
+}
+
+impl SpinIntegrator for ItoEulerMaruyama{
+    fn order(&self) -> u32{
+        1
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mut mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, omega1, omega2, chi1, chi2, b, ..} = work;
+        let dt = Aligned4xf64::from(delta_t);
+        let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+        let drift = if self.drift_correction {
+            Aligned4xf64::from(1.0 - *b * delta_t)
+        } else {
+            Aligned4xf64::from(1.0)
+        };
+        let renormalize_f = self.renormalize;
+
+        h_update_row(t0, eta, haml_fn, &mut h0.view_mut(), &m0);
+        Zip::from(&m0).and(&*h0).and(&mut *omega1).and(&mut mf).and(&*chi1).and(&*chi2)
+            .apply(|m0, h0, o1, mf, chi1, chi2|{
+                *o1 = h0 * dt + (chi1 + chi2) * half_sqrt;
+                *mf = m0 * drift + o1.cross(m0);
+                if renormalize_f{
+                    renormalize(mf, norm_sq(m0));
+                }
+            });
+        omega2.assign(&*omega1);
+    }
+}
+
+/// Peform a step of the Ito form of the Spin-Langevin equation with the Euler-Maruyama scheme.
+/// The parameters and noise conventions are the same as for `spin_langevin_step`.
+/// See `ItoEulerMaruyama` for the drift correction and the renormalization.
+pub fn spin_langevin_step_ito<Fh, R, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64, b: f64,
+    haml_fn: Fh,
+    rng_arr: & Vec<Mutex<R>>,
+    rand_xi_f: Fr,
+    drift_correction: bool
+) -> f64
+    where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+{
+    let scheme = ItoEulerMaruyama{drift_correction, ..ItoEulerMaruyama::default()};
+    spin_langevin_step_scheme(spins_t0, spins_tf, t0, delta_t, eta, b, haml_fn, rng_arr, rand_xi_f,
+                              &scheme)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Mutex;
+
+    use ndarray::{Array2, ArrayView1, ArrayViewMut1};
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use crate::{spin_langevin_step_scheme, MagnusScheme, SpinIntegrator};
+    use super::*;
+
+    /// Largest deviation from the 4th order Magnus reference after integrating a nonlinear
+    /// precession with dissipation
+    fn integration_error<I: SpinIntegrator>(scheme: &I, dt: f64) -> f64{
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(11);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let haml_fn = |t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            for (hi, mi) in h.iter_mut().zip(m.iter()){
+                hi[0] = Aligned4xf64::from(t.cos());
+                hi[1] = Aligned4xf64::from(0.0);
+                hi[2] = mi[2] + Aligned4xf64::from(1.0);
+            }
+        };
+        let mut spins0 : Array2<Vector3d4xf64> = Array2::from_elem((1, 1), Zero::zero());
+        spins0[(0, 0)][0] = Aligned4xf64::from(0.6);
+        spins0[(0, 0)][2] = Aligned4xf64::from(0.8);
+        let propagate = |dt: f64, scheme: &dyn Fn(&Array2<Vector3d4xf64>, &mut Array2<Vector3d4xf64>, f64)|{
+            let n = (2.0 / dt).round() as usize;
+            let mut m0 = spins0.clone();
+            let mut mf = spins0.clone();
+            for i in 0..n{
+                scheme(&m0, &mut mf, i as f64 * dt);
+                std::mem::swap(&mut m0, &mut mf);
+            }
+            m0
+        };
+        let m_ref = propagate(2.0e-3, &|m0, mf, t| {
+            spin_langevin_step_scheme(m0, mf, t, 2.0e-3, 0.1, 0.0, &haml_fn, &rng_arr,
+                                      |_r| Zero::zero(), &MagnusScheme::Magnus4);
+        });
+        let m = propagate(dt, &|m0, mf, t| {
+            spin_langevin_step_scheme(m0, mf, t, dt, 0.1, 0.0, &haml_fn, &rng_arr,
+                                      |_r| Zero::zero(), scheme);
+        });
+        let d = m[(0, 0)] - m_ref[(0, 0)];
+        norm_sq(&d).map(f64::sqrt).dat[0]
+    }
+
+    #[test]
+    fn test_ito_drift_correction(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(5);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let rand_xi_f = |r: &mut Xoshiro256Plus| Vector3d4xf64::from_fn(|_i, _j| {
+            let mut x = Aligned4xf64::from(0.0);
+            for xi in x.dat.iter_mut(){
+                *xi = r.sample(rand_distr::StandardNormal);
+            }
+            x
+        });
+        // Without a Hamiltonian, the corrected scheme keeps the mean squared norm at one
+        // while the uncorrected scheme grows it by a factor (1 + 2 b dt) per step
+        let (b, dt, n) = (0.1, 0.01, 100);
+        let mean_sq = |drift_correction: bool| -> f64{
+            let scheme = ItoEulerMaruyama{drift_correction, renormalize: false};
+            let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((32, 16), Zero::zero());
+            for m in m0.iter_mut(){
+                m[2] = Aligned4xf64::from(1.0);
+            }
+            let mut mf = m0.clone();
+            for i in 0..n{
+                spin_langevin_step_scheme(&m0, &mut mf, i as f64 * dt, dt, 0.0, b,
+                                          |_t, _m, h| h.fill(Zero::zero()), &rng_arr, rand_xi_f, &scheme);
+                std::mem::swap(&mut m0, &mut mf);
+            }
+            m0.iter().map(|m| norm_sq(m).mean_reduce()).sum::<f64>() / m0.len() as f64
+        };
+        let corrected = mean_sq(true);
+        let uncorrected = mean_sq(false);
+        println!("Mean squared norms: corrected {}, uncorrected {}", corrected, uncorrected);
+        assert!((corrected - 1.0).abs() < 0.05);
+        assert!((uncorrected - (1.0 + 2.0 * b * dt).powi(n)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_integrators_second_order(){
+        fn ratio<I: SpinIntegrator>(scheme: &I) -> f64{
+            integration_error(scheme, 0.02) / integration_error(scheme, 0.01)
+        }
+        let heun = ratio(&StochasticHeun);
+        let dm = ratio(&DepondtMertens);
+        let sib = ratio(&SemiImplicitB);
+        let midpoint = ratio(&ImplicitMidpoint::default());
+        println!("Error ratios: Heun {}, DM {}, SIB {}, Midpoint {}", heun, dm, sib, midpoint);
+        for &r in [heun, dm, sib, midpoint].iter(){
+            assert!(r > 3.5 && r < 4.5);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Classical Ising problems
+//!
+//!     E(s) = \sum_i h_i s_i + \sum_{(i, j)} J_{ij} s_i s_j + offset,      s_i = \pm 1
+//!
+//! This is the Pauli-level problem Hamiltonian H_P of an annealing run, with each coupled
+//! pair (i, j) listed once.
+
+use crate::hamiltonian::SparseCouplingHamiltonian;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IsingProblem{
+    pub h: Vec<f64>,
+    pub couplings: Vec<(usize, usize, f64)>,
+    pub offset: f64
+}
+
+impl IsingProblem{
+    pub fn new(h: Vec<f64>, couplings: Vec<(usize, usize, f64)>) -> Self{
+        let n = h.len();
+        assert!(couplings.iter().all(|&(i, j, _)| i < n && j < n && i != j),
+                "IsingProblem: invalid coupling indices");
+        Self{h, couplings, offset: 0.0}
+    }
+
+    pub fn num_spins(&self) -> usize{
+        self.h.len()
+    }
+
+    /// Energy of a configuration of \pm 1 spins
+    pub fn energy(&self, s: &[i8]) -> f64{
+        assert_eq!(s.len(), self.num_spins(), "IsingProblem: mismatching number of spins");
+        let e_h : f64 = self.h.iter().zip(s.iter()).map(|(&h, &si)| h * si as f64).sum();
+        let e_j : f64 = self.couplings.iter().map(|&(i, j, jij)| jij * (s[i] * s[j]) as f64).sum();
+
+        e_h + e_j + self.offset
+    }
+
+    /// The problem as a sparse coupling Hamiltonian on the spin vectors (without the offset)
+    pub fn to_hamiltonian(&self) -> SparseCouplingHamiltonian{
+        SparseCouplingHamiltonian::ising(&self.h, &self.couplings)
+    }
+}
@@ -1,7 +1,7 @@
 use itertools::Itertools;
 use nalgebra::{Vector3, Matrix3};
 
-use ndarray::{Array2, ArrayView1, ArrayView2, ArrayView3, ArrayViewMut1, Axis, Zip, Array1};
+use ndarray::{Array2, ArrayView1, ArrayView2, ArrayView3, ArrayViewMut1, ArrayViewMut2, Axis, Zip, Array1, Array3};
 use ndarray::parallel::prelude::*;
 use num_traits::Zero;
 use rand::Rng;
@@ -12,6 +12,22 @@ use simd_phys::vf64::Aligned4xf64;
 use std::sync::{Mutex, MutexGuard};
 use std::ops::DerefMut;
 
+pub mod adaptive;
+pub mod bath;
+pub mod formats;
+pub mod graphs;
+pub mod hamiltonian;
+pub mod integrators;
+pub mod ising;
+pub mod llb;
+pub mod llg;
+pub mod memory;
+pub mod noise;
+pub mod philox;
+pub mod planted;
+pub mod protocol;
+pub mod readout;
+pub mod torque;
 
 pub static MAX_AVG_ANGULAR_FIELD : f64 = std::f64::consts::PI;
 
@@ -30,14 +46,58 @@ impl StepResult{
     }
 }
 
+/// Contents of the unused lanes of the last chunk when n is not a multiple of 4
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PaddingPolicy{
+    /// Zero vectors. These are inert under fields that vanish on the padding lanes,
+    /// but cannot be renormalized.
+    Zero,
+    /// Ghost spins along +z, which are unit vectors and can be renormalized safely.
+    /// Hamiltonians must not couple to them.
+    Ghost
+}
+
+impl Default for PaddingPolicy{
+    fn default() -> Self{
+        PaddingPolicy::Zero
+    }
+}
+
+/// Number of 4xf64 chunks holding n spins
+pub fn num_chunks(n: usize) -> usize{
+    if n == 0 { 0 } else { (n-1)/4 + 1 }
+}
+
+/// Pack per-spin values into chunks of four lanes, with zeros in the padding lanes
+pub(crate) fn pack_lanes(values: &[f64]) -> Vec<Aligned4xf64>{
+    let n = values.len();
+    (0..num_chunks(n)).map(|c|{
+        let mut x = Aligned4xf64::from(0.0);
+        for (l, xl) in x.dat.iter_mut().enumerate(){
+            if 4 * c + l < n{
+                *xl = values[4 * c + l];
+            }
+        }
+        x
+    }).collect()
+}
+
+/// Pack an (n, 3) array into chunks, with zero padding
 pub fn xyz_to_array_chunks(arr: ArrayView2<f64>,
-                           mut chunk_array: ArrayViewMut1<Vector3d4xf64>) {
+                           chunk_array: ArrayViewMut1<Vector3d4xf64>) {
+    xyz_to_array_chunks_padded(arr, chunk_array, PaddingPolicy::Zero)
+}
+
+/// Pack an (n, 3) array into chunks, where spin i is placed in lane i % 4 of chunk i / 4
+pub fn xyz_to_array_chunks_padded(arr: ArrayView2<f64>,
+                                  mut chunk_array: ArrayViewMut1<Vector3d4xf64>,
+                                  padding: PaddingPolicy) {
     let shape = arr.shape();
     if shape[1] != 3{
         panic!("xyz_to_array_chunks: 3 spatial dimensions required.");
     }
     let n = shape[0];
-    let n_ch = (n-1)/4 + 1;
+    let n_ch = num_chunks(n);
     if chunk_array.shape()[0] != n_ch{
         panic!("xyz_to_array_chunks: mismatching chunk size")
     }
@@ -56,6 +116,76 @@ pub fn xyz_to_array_chunks(arr: ArrayView2<f64>,
         }
     }
 
+    if n % 4 != 0{
+        let last = &mut chunk_array[n_ch - 1];
+        for l in n % 4..4{
+            last[0].dat[l] = 0.0;
+            last[1].dat[l] = 0.0;
+            last[2].dat[l] = match padding{
+                PaddingPolicy::Zero => 0.0,
+                PaddingPolicy::Ghost => 1.0
+            };
+        }
+    }
+}
+
+/// Unpack the first n spins of the chunks into an (n, 3) array. Padding lanes are ignored.
+pub fn array_chunks_to_xyz(chunk_array: ArrayView1<Vector3d4xf64>,
+                           mut arr: ArrayViewMut2<f64>) {
+    let shape = arr.shape();
+    if shape[1] != 3{
+        panic!("array_chunks_to_xyz: 3 spatial dimensions required.");
+    }
+    let n = shape[0];
+    if chunk_array.shape()[0] != num_chunks(n){
+        panic!("array_chunks_to_xyz: mismatching chunk size")
+    }
+
+    for (mut xyz_chunk, chunk_4xf64) in arr.axis_chunks_iter_mut(Axis(0), 4)
+        .zip(chunk_array.iter())
+    {
+        let mut xyz_chunk_t = xyz_chunk.view_mut().reversed_axes();
+        for (mut x1, x2) in xyz_chunk_t.genrows_mut().into_iter().zip(chunk_4xf64.iter()){
+            for (x1i, &x2i) in x1.iter_mut().zip(x2.dat.iter()){
+                *x1i = x2i;
+            }
+        }
+    }
+}
+
+/// Allocate and pack the chunks of an (n, 3) array
+pub fn pack_xyz(arr: ArrayView2<f64>, padding: PaddingPolicy) -> Array1<Vector3d4xf64>{
+    let mut chunks = Array1::from_elem(num_chunks(arr.shape()[0]), Zero::zero());
+    xyz_to_array_chunks_padded(arr, chunks.view_mut(), padding);
+    chunks
+}
+
+/// Allocate and unpack the first n spins of the chunks into an (n, 3) array
+pub fn unpack_xyz(chunk_array: ArrayView1<Vector3d4xf64>, n: usize) -> Array2<f64>{
+    let mut arr = Array2::zeros((n, 3));
+    array_chunks_to_xyz(chunk_array, arr.view_mut());
+    arr
+}
+
+/// Pack a (replicas, n, 3) array into the (replicas, chunks) spin arrays of `spin_langevin_step`
+pub fn pack_xyz_rows(arr: ArrayView3<f64>, padding: PaddingPolicy) -> Array2<Vector3d4xf64>{
+    let shape = arr.shape();
+    let mut chunks = Array2::from_elem((shape[0], num_chunks(shape[1])), Zero::zero());
+    for (xyz, row) in arr.outer_iter().zip(chunks.genrows_mut()){
+        xyz_to_array_chunks_padded(xyz, row, padding);
+    }
+    chunks
+}
+
+/// Unpack the first n spins of each row of a (replicas, chunks) spin array into a
+/// (replicas, n, 3) array
+pub fn unpack_xyz_rows(chunk_array: ArrayView2<Vector3d4xf64>, n: usize) -> Array3<f64>{
+    let rows = chunk_array.shape()[0];
+    let mut arr = Array3::zeros((rows, n, 3));
+    for (row, xyz) in chunk_array.genrows().into_iter().zip(arr.outer_iter_mut()){
+        array_chunks_to_xyz(row, xyz);
+    }
+    arr
 }
 
 /// Evaluates v in the dynamical spin-langevin equation
@@ -183,7 +313,9 @@ pub struct SpinLangevinRowWorkpad{
     pub omega1: Array1<Vector3d4xf64>,
     pub omega2: Array1<Vector3d4xf64>,
     pub chi1: Array1<Vector3d4xf64>,
-    pub chi2: Array1<Vector3d4xf64>
+    pub chi2: Array1<Vector3d4xf64>,
+    /// Noise strength b of the step, for schemes with a noise-induced drift
+    pub b: f64
 }
 
 impl SpinLangevinRowWorkpad{
@@ -192,7 +324,8 @@ impl SpinLangevinRowWorkpad{
         Self{
             h0: Array1::from_elem(shape, Zero::zero()), h1: Array1::from_elem(shape, Zero::zero()), h2:  Array1::from_elem(shape, Zero::zero()),
             omega1:  Array1::from_elem(shape, Zero::zero()), omega2:  Array1::from_elem(shape, Zero::zero()),
-            chi1: Array1::from_elem(shape, Zero::zero()), chi2: Array1::from_elem(shape, Zero::zero())
+            chi1: Array1::from_elem(shape, Zero::zero()), chi2: Array1::from_elem(shape, Zero::zero()),
+            b: 0.0
         }
     }
 
@@ -614,6 +747,189 @@ where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
     m_update_row(&omega_f.view(), &m0, &mut mf);
 }
 
+/// Number of fixed point iterations of the 4th order nonlinear Magnus propagator
+const MAGNUS4_ITERATIONS: usize = 3;
+
+/// Magnus generator over [0, \tau] of the linear field  g(s) = a + b s, plus the noise increment w
+///     \Omega(\tau) = a \tau + b \tau^2 / 2 + (\tau^3 / 12) (b \cross a) + w
+#[inline]
+fn magnus4_generator(a: &Vector3d4xf64, b: &Vector3d4xf64, tau: f64, w: &Vector3d4xf64) -> Vector3d4xf64{
+    a * Aligned4xf64::from(tau) + b * Aligned4xf64::from(tau * tau / 2.0)
+        + b.cross(a) * Aligned4xf64::from(tau * tau * tau / 12.0) + w
+}
+
+/// The nonlinear Magnus Expansion to 4th order in the deterministic part is as follows:
+///
+/// With the Gauss-Legendre nodes  c_{1,2} = 1/2 \mp \sqrt{3}/6,  t_a = t_0 + c_1 \delta_t,
+/// t_b = t_0 + c_2 \delta_t, the fields at the nodes are first estimated as
+///     H_a = H(t_a, m_0),     H_b = H(t_b, m_0)
+/// The fields are interpolated linearly in time, g(s) = a + b s, and the spins at the nodes
+/// are corrected by the generators
+///     \Omega(\tau) = a \tau + b \tau^2 / 2 + (\tau^3 / 12) (b \cross a) + W(\tau)
+///     m_a = \exp{\Omega(c_1 \delta_t)} m_0,      m_b = \exp{\Omega(c_2 \delta_t)} m_0
+///     H_a = H(t_a, m_a),     H_b = H(t_b, m_b)
+/// Each of the MAGNUS4_ITERATIONS corrections gains one order in the node fields.
+/// W(\tau) is the piecewise linear Brownian path through  \sqrt{\delta_t/2} \chi_1  and
+/// \sqrt{\delta_t/2} (\chi_1 + \chi_2).
+///
+/// Final propagation, with the commutator [\Omega_a, \Omega_b] evaluated as a cross product:
+///     \Omega = (\delta_t / 2) (H_a + H_b) + (\sqrt{3} \delta_t^2 / 12) (H_b \cross H_a)
+///             + \sqrt{\delta_t/2} (\chi_1 + \chi_2)
+///     m[\delta_t] :=  \exp{\Omega} m_0
+///
+/// On exit, the propagator evaluated with the node fields of the previous iteration will be stored
+/// in `omega1` and the final propagator will be stored in `omega2`
+///
+fn spin_langevin_step_row_m4<Fh>(
+    t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+    m0: ArrayView1<Vector3d4xf64>,
+    mut mf: ArrayViewMut1<Vector3d4xf64>,
+    haml0: ArrayViewMut1<Vector3d4xf64>,
+    haml1: ArrayViewMut1<Vector3d4xf64>,
+    haml2: ArrayViewMut1<Vector3d4xf64>,
+    mut omega1: ArrayViewMut1<Vector3d4xf64>,
+    mut omega2: ArrayViewMut1<Vector3d4xf64>,
+    noise1: ArrayView1<Vector3d4xf64>,
+    noise2: ArrayView1<Vector3d4xf64>
+)
+    where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+{
+    let sqrt3 = 3.0_f64.sqrt();
+    let c1 = 0.5 - sqrt3 / 6.0;
+    let c2 = 0.5 + sqrt3 / 6.0;
+    let ta = t0 + c1 * delta_t;
+    let tb = t0 + c2 * delta_t;
+    let h_update = |t: f64, h: &mut ArrayViewMut1<Vector3d4xf64>, m: & ArrayView1<Vector3d4xf64> |{
+        h_update_row(t, eta, haml_fn, h, m);
+    };
+    let half_sqrt = Aligned4xf64::from((delta_t / 2.0).sqrt());
+    let inv_dc = Aligned4xf64::from(1.0 / ((c2 - c1) * delta_t));
+    let w1 = Aligned4xf64::from(2.0 * c1);
+    let w2 = Aligned4xf64::from(2.0 * c2 - 1.0);
+    let final_generator = |ha: &Vector3d4xf64, hb: &Vector3d4xf64, chi1: &Vector3d4xf64, chi2: &Vector3d4xf64|{
+        (ha + hb) * Aligned4xf64::from(delta_t / 2.0)
+            + hb.cross(ha) * Aligned4xf64::from(sqrt3 * delta_t * delta_t / 12.0)
+            + (chi1 + chi2) * half_sqrt
+    };
+
+    let mut haml_a = haml0;
+    let mut haml_b = haml1;
+    let mut omega_lo = haml2;
+
+    h_update(ta, &mut haml_a, &m0);
+    h_update(tb, &mut haml_b, &m0);
+
+    for it in 0..MAGNUS4_ITERATIONS{
+        // Node generators from the linear interpolation of the current node fields
+        ndarray::Zip::from(haml_a.view()).and(haml_b.view())
+            .and(omega1.view_mut()).and(omega2.view_mut())
+            .and(noise1.view()).and(noise2.view())
+            .apply(|ha, hb, oa, ob, chi1, chi2|{
+                let b = (hb - ha) * inv_dc;
+                let a = ha - b * Aligned4xf64::from(c1 * delta_t);
+                *oa = magnus4_generator(&a, &b, c1 * delta_t, &(chi1 * (half_sqrt * w1)));
+                *ob = magnus4_generator(&a, &b, c2 * delta_t,
+                                        &((chi1 + chi2 * w2) * half_sqrt));
+            });
+        if it + 1 == MAGNUS4_ITERATIONS{
+            // Lower order estimate of the full propagator for the post-condition
+            ndarray::Zip::from(haml_a.view()).and(haml_b.view()).and(omega_lo.view_mut())
+                .and(noise1.view()).and(noise2.view())
+                .apply(|ha, hb, o, chi1, chi2|{
+                    *o = final_generator(ha, hb, chi1, chi2);
+                });
+        }
+        // Correct the node fields
+        m_update_row(&omega1.view(), &m0, &mut mf);
+        h_update(ta, &mut haml_a, &mf.view());
+        m_update_row(&omega2.view(), &m0, &mut mf);
+        h_update(tb, &mut haml_b, &mf.view());
+    }
+
+    ndarray::Zip::from(haml_a.view()).and(haml_b.view()).and(omega2.view_mut())
+        .and(noise1.view()).and(noise2.view())
+        .apply(|ha, hb, o, chi1, chi2|{
+            *o = final_generator(ha, hb, chi1, chi2);
+        });
+    omega1.assign(&omega_lo);
+
+    // Propagate m[0] to m[\delta_t]
+    m_update_row(&omega2.view(), &m0, &mut mf);
+}
+
+/// Selects the nonlinear Magnus propagator applied to each row of spins
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MagnusScheme{
+    /// 2nd order propagator with Simpson-weighted fields and one corrector stage
+    Magnus2,
+    /// 4th order propagator (in the deterministic part) with Gauss-Legendre nodes and
+    /// the commutator term
+    Magnus4
+}
+
+impl Default for MagnusScheme{
+    fn default() -> Self {
+        MagnusScheme::Magnus2
+    }
+}
+
+/// A single-step integration scheme of the Spin-Langevin equation, applied to one row of spins
+/// at a time by the parallel drivers `spin_langevin_step_scheme` and `spin_langevin_step_noise`
+/// and by the adaptive `SpinLangevinIntegrator`.
+///
+/// All schemes share the conventions of `spin_langevin_step`: the Hamiltonian closure `haml_fn`,
+/// the dissipation strength `eta` (added to the local fields by `h_update_row`), and the noise arrays
+/// `work.chi1` and `work.chi2`, already scaled by \sqrt{b}, so that the Brownian increments over
+/// the two half-steps are  \sqrt{\delta_t/2} \chi_1  and  \sqrt{\delta_t/2} \chi_2.
+/// The drivers store the noise strength b of the step in `work.b`.
+pub trait SpinIntegrator : Sync{
+    /// Order of the deterministic part of the scheme, used for step size control
+    fn order(&self) -> u32;
+
+    /// Propagate the row of spins `m0` at time t0 to `mf` at time t0 + delta_t.
+    /// On exit, a lower order estimate of the effective propagator (rotation generator)
+    /// of the step must be stored in `work.omega1` and the final propagator in `work.omega2`
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>);
+}
+
+impl SpinIntegrator for MagnusScheme{
+    fn order(&self) -> u32{
+        match self{
+            MagnusScheme::Magnus2 => 2,
+            MagnusScheme::Magnus4 => 4
+        }
+    }
+
+    fn step_row<Fh>(
+        &self,
+        t0: f64, delta_t: f64, eta: f64, haml_fn: &Fh,
+        m0: ArrayView1<Vector3d4xf64>,
+        mf: ArrayViewMut1<Vector3d4xf64>,
+        work: &mut SpinLangevinRowWorkpad
+    )
+        where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
+    {
+        let SpinLangevinRowWorkpad{h0, h1, h2, omega1, omega2, chi1, chi2, ..} = work;
+        match self{
+            MagnusScheme::Magnus2 => spin_langevin_step_row(
+                t0, delta_t, eta, haml_fn, m0, mf,
+                h0.view_mut(), h1.view_mut(), h2.view_mut(), omega1.view_mut(), omega2.view_mut(),
+                chi1.view(), chi2.view()),
+            MagnusScheme::Magnus4 => spin_langevin_step_row_m4(
+                t0, delta_t, eta, haml_fn, m0, mf,
+                h0.view_mut(), h1.view_mut(), h2.view_mut(), omega1.view_mut(), omega2.view_mut(),
+                chi1.view(), chi2.view())
+        }
+    }
+}
+
 
 /// Peform a step of the Spin-Langevin stochastic differential equation (Stratonovich form)
 /// using a 2nd order nonlinear Magnus propagator
@@ -644,6 +960,8 @@ where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>)
 ///     rescaled by 2^K. Additionally, the single-qubit coupling $\eta$ of the open system dynamics
 ///     should be rescaled by 2 in the Spin-Langevin equation. Failing to rescale will result
 ///     in (likely incorrect) dynamics over an incorrect time scale.
+///     `hamiltonian::AnnealingHamiltonian` applies these factors to annealing problems, and
+///     `hamiltonian::annealing::spin_eta` rescales \eta.
 ///
 ///     Nuclear/Particle physics applications should similarly rescale by the gyromagnetic ratio
 ///     where appropriate so that the Hamiltonian is in terms of S_i operators rather than
@@ -674,6 +992,32 @@ pub fn spin_langevin_step< Fh, R, Fr>(
           R: Rng + Send + Sync,
           Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
 {
+    spin_langevin_step_scheme(spins_t0, spins_tf, t0, delta_t, eta, b, haml_fn, rng_arr, rand_xi_f,
+                              &MagnusScheme::Magnus2)
+}
+
+/// Peform a step of the Spin-Langevin stochastic differential equation (Stratonovich form)
+/// as in `spin_langevin_step`, using the integration scheme `scheme`.
+///
+/// `MagnusScheme::Magnus4` is 4th order in the deterministic part of the equation, and is
+/// intended for low temperature or noiseless (b = 0) dynamics, where the precession phase error
+/// of the 2nd order propagator dominates. See the `integrators` module for other schemes.
+///
+/// Returns the average magnitude of the final propagator of each step.
+pub fn spin_langevin_step_scheme<I, Fh, R, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64, b: f64,
+    haml_fn: Fh,
+    rng_arr: & Vec<Mutex<R>>,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> f64
+    where I: SpinIntegrator,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+{
 
     //assert_eq!(spins_t0.raw_dim(), work.h0.raw_dim());
     assert_eq!(spins_tf.raw_dim(), spins_t0.raw_dim());
@@ -681,7 +1025,6 @@ pub fn spin_langevin_step< Fh, R, Fr>(
     let h_shape = (h_shape[0], h_shape[1]);
     assert!(b >= 0.0, "Stochastic strength must be non-negative");
     let num_threads = rayon::current_num_threads();
-    let rows_per_thread = h_shape.0  / num_threads;
     assert!(rng_arr.len() >= num_threads, "Insufficient number of RNGs for multithreading");
     let b_sqrt = Aligned4xf64::from(b.sqrt());
 
@@ -712,10 +1055,8 @@ pub fn spin_langevin_step< Fh, R, Fr>(
                     *chi2 = rand_xi_f(rng) * b_sqrt;
                 }
                 // Spin-langevin propagator
-                spin_langevin_step_row(t0, delta_t, eta, &haml_fn, m0, mf,
-                                       work.h0.view_mut(), work.h1.view_mut(), work.h2.view_mut(),
-                                       work.omega1.view_mut(), work.omega2.view_mut(),
-                                       work.chi1.view(), work.chi2.view());
+                work.b = b;
+                scheme.step_row(t0, delta_t, eta, &haml_fn, m0, mf, work);
                 // Evaluate average \Omega_{22} for row
                 let avg_hdt = avg_field_row(&work.omega2.view());
 
@@ -728,6 +1069,75 @@ pub fn spin_langevin_step< Fh, R, Fr>(
 
 }
 
+/// Average generator norms of a step taken by `spin_langevin_step_noise`
+#[derive(Copy, Clone, Debug)]
+pub struct StepNorms{
+    /// Average |\Omega_{12}| (stage one, or lower order, propagator)
+    pub omega12: f64,
+    /// Average |\Omega_{22}| (stage two, or final, propagator)
+    pub omega22: f64,
+    /// Average |\Omega_{22} - \Omega_{12}|
+    pub delta: f64
+}
+
+/// Peform a step of the Spin-Langevin equation using the integration scheme `scheme`,
+/// as in `spin_langevin_step_scheme`, but with the stochastic increments supplied by the caller.
+///
+/// noise1, noise2: Noise arrays with the same shape as the spins. The Brownian increments over
+///     the first and second half-steps are  \sqrt{\delta_t/2} \chi_1  and  \sqrt{\delta_t/2} \chi_2
+///     respectively, i.e. the arrays are already scaled by \sqrt{b}
+/// b: Noise strength of white noise arrays, passed to the scheme in `work.b`. Colored noise,
+///     which has no noise-induced drift, passes zero
+///
+/// Returns the averages of the lower order (stage one) and final (stage two) propagator norms,
+/// as well as the average norm of their difference, which is a local error estimate of the
+/// deterministic part of the step. The noise contributions to both propagators are identical and
+/// cancel in the difference.
+pub fn spin_langevin_step_noise<I, Fh>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64, b: f64,
+    haml_fn: Fh,
+    noise1: &Array2<Vector3d4xf64>, noise2: &Array2<Vector3d4xf64>,
+    scheme: &I
+) -> StepNorms
+    where I: SpinIntegrator,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync
+{
+    assert_eq!(spins_tf.raw_dim(), spins_t0.raw_dim());
+    assert_eq!(noise1.raw_dim(), spins_t0.raw_dim());
+    assert_eq!(noise2.raw_dim(), spins_t0.raw_dim());
+    let h_shape = spins_tf.shape();
+    let h_shape = (h_shape[0], h_shape[1]);
+
+    let (o12, o22, delta) : (f64, f64, f64) =
+    Zip::from(spins_t0.axis_iter(Axis(0)))
+        .and(spins_tf.axis_iter_mut(Axis(0)))
+        .and(noise1.axis_iter(Axis(0)))
+        .and(noise2.axis_iter(Axis(0)))
+        .into_par_iter().map_init(
+            || SpinLangevinRowWorkpad::from_shape(h_shape.1),
+            |work: &mut SpinLangevinRowWorkpad, (m0, mf, chi1, chi2)|{
+                work.chi1.assign(&chi1);
+                work.chi2.assign(&chi2);
+                work.b = b;
+                scheme.step_row(t0, delta_t, eta, &haml_fn, m0, mf, work);
+                // The difference \Omega_{22} - \Omega_{12} is stored in h0
+                Zip::from(work.h0.view_mut()).and(work.omega1.view()).and(work.omega2.view())
+                    .apply(|d, o1, o2|{
+                        *d = o2 - o1;
+                    });
+
+                (avg_field_row(&work.omega1.view()), avg_field_row(&work.omega2.view()),
+                 avg_field_row(&work.h0.view()))
+            })
+        .reduce(|| (0.0, 0.0, 0.0),
+                |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2));
+    let n = h_shape.0 as f64;
+
+    StepNorms{omega12: o12 / n, omega22: o22 / n, delta: delta / n}
+}
+
 pub fn spin_langevin_step_old<'a, Fh, R, Fr>(
     m0: &Array2<Vector3d4xf64>, mf: &mut Array2<Vector3d4xf64>,
     t0: f64, delta_t : f64,
@@ -881,6 +1291,22 @@ mod tests{
     use super::*;
     use simd_phys::vf64::Aligned4xf64;
 
+    #[test]
+    fn test_chunk_packing(){
+        let xyz = Array3::from_shape_fn((3, 6, 3), |(r, i, a)| (100 * r + 10 * i + a) as f64);
+        let chunks = pack_xyz_rows(xyz.view(), PaddingPolicy::Ghost);
+        assert_eq!(chunks.shape(), &[3, 2]);
+        assert_eq!(chunks[(1, 1)][0].dat, [140.0, 150.0, 0.0, 0.0]);
+        assert_eq!(chunks[(1, 1)][2].dat, [142.0, 152.0, 1.0, 1.0]);
+        assert_eq!(unpack_xyz_rows(chunks.view(), 6), xyz);
+
+        let mut zero = chunks.row(2).to_owned();
+        xyz_to_array_chunks(xyz.index_axis(Axis(0), 0), zero.view_mut());
+        assert_eq!(zero[1][2].dat, [42.0, 52.0, 0.0, 0.0]);
+        assert_eq!(unpack_xyz(zero.view(), 6), xyz.index_axis(Axis(0), 0));
+        assert_eq!(pack_xyz(xyz.index_axis(Axis(0), 0), PaddingPolicy::Zero), zero);
+    }
+
     #[test]
     fn test_spin_langevin_dmdt(){
         let num_threads = rayon::current_num_threads();
@@ -925,6 +1351,52 @@ mod tests{
         //sl_add_dissipative(&mut haml.view_mut(), & spins.view(), 0.1);
     }
 
+    #[test]
+    fn test_spin_langevin_magnus4_order(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(7);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        // Nonlinear, time-dependent field: h = (cos t, 0, 1 + m_z)
+        let haml_fn = |t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            for (hi, mi) in h.iter_mut().zip(m.iter()){
+                hi[0] = Aligned4xf64::from(t.cos());
+                hi[1] = Aligned4xf64::from(0.0);
+                hi[2] = mi[2] + Aligned4xf64::from(1.0);
+            }
+        };
+        let mut spins0 : Array2<Vector3d4xf64> = Array2::from_elem((1, 1), Zero::zero());
+        spins0[(0, 0)][0] = Aligned4xf64::from(0.6);
+        spins0[(0, 0)][2] = Aligned4xf64::from(0.8);
+        let propagate = |dt: f64, scheme: MagnusScheme| -> Array2<Vector3d4xf64>{
+            let n = (2.0 / dt).round() as usize;
+            let mut m0 = spins0.clone();
+            let mut mf = spins0.clone();
+            for i in 0..n{
+                spin_langevin_step_scheme(&m0, &mut mf, i as f64 * dt, dt, 0.0, 0.0, &haml_fn,
+                                          &rng_arr, |_r| Zero::zero(), &scheme);
+                std::mem::swap(&mut m0, &mut mf);
+            }
+            m0
+        };
+        let err = |m: &Array2<Vector3d4xf64>, m_ref: &Array2<Vector3d4xf64>| -> f64{
+            let d = m[(0, 0)] - m_ref[(0, 0)];
+            (d[0]*d[0] + d[1]*d[1] + d[2]*d[2]).map(f64::sqrt).dat[0]
+        };
+        let m_ref = propagate(1.0e-3, MagnusScheme::Magnus4);
+        let e1 = err(&propagate(0.1, MagnusScheme::Magnus4), &m_ref);
+        let e2 = err(&propagate(0.05, MagnusScheme::Magnus4), &m_ref);
+        println!("Magnus4 errors: {} {} (ratio {})", e1, e2, e1 / e2);
+        assert!(e1 / e2 > 12.0);
+        let e1 = err(&propagate(0.1, MagnusScheme::Magnus2), &m_ref);
+        let e2 = err(&propagate(0.05, MagnusScheme::Magnus2), &m_ref);
+        println!("Magnus2 errors: {} {} (ratio {})", e1, e2, e1 / e2);
+        assert!((e1 / e2 - 4.0).abs() < 0.5);
+    }
+
     #[test]
     fn test_spin_langevin_f64_dmdt(){
         let sx : Vector3<f64> = Vector3::new(1.0, 0.0, 0.0);
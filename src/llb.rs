@@ -0,0 +1,246 @@
+//! Landau-Lifshitz-Bloch stepping with a variable spin length
+//!
+//! The Magnus propagator rotates the spins and preserves |m| exactly. The LLB equation adds the
+//! longitudinal relaxation of the spin length r = |m| toward the equilibrium magnetization m_e(T),
+//!     dr = \lambda ( h \cdot \hat{m} + (1 / 2\chi_\parallel) (1 - r^2 / m_e^2) r ) dt + \sqrt{b_\parallel} dW,
+//! where \lambda is the longitudinal damping and \chi_\parallel the longitudinal susceptibility.
+//! This is overdamped motion of r in the free energy
+//!     F(r) = (r^2 - m_e^2)^2 / (8 \chi_\parallel m_e^2) - r h \cdot \hat{m}
+//! whose stationary distribution is  \propto \exp(-F(r) / k_B T)  when b_\parallel = 2 \lambda k_B T.
+//!
+//! A step of `spin_langevin_step_llb` is a rotation by `spin_langevin_step_counter` followed by
+//! the radial update of `llb_radial_update` (Euler-Maruyama, reflected at r = 0) on the same arrays.
+//! Since the transverse dissipation of a spin of length r is \eta r^2 |h|, the LLB transverse damping
+//! \alpha_\perp is recovered near equilibrium with \eta = \alpha_\perp / m_e^2.
+//! The explicit radial update requires  \lambda \delta_t / \chi_\parallel \lesssim 1.
+
+use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1, Axis};
+use ndarray::parallel::prelude::*;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use simd_phys::r3::Vector3d4xf64;
+
+use crate::philox::{counter_rng, par_counter_rows, spin_langevin_step_counter, PhiloxRng};
+use crate::SpinIntegrator;
+
+/// First block of the radial noise in the generator of a chunk. The rotation of the same step draws
+/// from the first blocks of the generator, so the two noise sources do not overlap.
+const RADIAL_BLOCK: u32 = 1 << 31;
+
+/// Mean field equilibrium magnetization of classical spins, the solution of
+///     m = L(3 m T_c / T),     L(x) = \coth(x) - 1/x
+pub fn curie_weiss_magnetization(t_over_tc: f64) -> f64{
+    assert!(t_over_tc >= 0.0, "curie_weiss_magnetization: negative temperature");
+    if t_over_tc >= 1.0{
+        return 0.0;
+    }
+    if t_over_tc == 0.0{
+        return 1.0;
+    }
+    let langevin = |x: f64| if x < 1.0e-4 { x / 3.0 } else { 1.0 / x.tanh() - 1.0 / x };
+    // Bisection on m - L(3 m / \tau), which is negative below the nonzero root
+    let (mut lo, mut hi) = (1.0e-12, 1.0);
+    for _ in 0..100{
+        let mid = 0.5 * (lo + hi);
+        if mid - langevin(3.0 * mid / t_over_tc) < 0.0 { lo = mid; } else { hi = mid; }
+    }
+    0.5 * (lo + hi)
+}
+
+/// Longitudinal relaxation parameters of the LLB equation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LlbParameters{
+    /// Longitudinal damping \lambda
+    pub lambda: f64,
+    /// Equilibrium magnetization m_e(T)
+    pub m_e: f64,
+    /// Longitudinal susceptibility \chi_\parallel
+    pub chi_par: f64,
+    /// Longitudinal noise strength b_\parallel
+    pub b: f64
+}
+
+impl LlbParameters{
+    pub fn new(lambda: f64, m_e: f64, chi_par: f64, b: f64) -> Self{
+        assert!(lambda >= 0.0 && b >= 0.0, "LlbParameters: damping and noise must be non-negative");
+        assert!(m_e > 0.0 && chi_par > 0.0, "LlbParameters: m_e and chi_par must be positive");
+        Self{lambda, m_e, chi_par, b}
+    }
+
+    /// Parameters at the temperature k_B T, with b_\parallel = 2 \lambda k_B T
+    pub fn thermal(lambda: f64, m_e: f64, chi_par: f64, kt: f64) -> Self{
+        assert!(kt >= 0.0, "LlbParameters: negative temperature");
+        Self::new(lambda, m_e, chi_par, 2.0 * lambda * kt)
+    }
+
+    /// Drift of the spin length r with the longitudinal field h_par = h \cdot \hat{m}
+    #[inline]
+    fn drift(&self, r: f64, h_par: f64) -> f64{
+        self.lambda * (h_par + (1.0 - r * r / (self.m_e * self.m_e)) * r / (2.0 * self.chi_par))
+    }
+}
+
+/// Uniformly random unit vector
+fn random_direction<R: Rng>(rng: &mut R) -> [f64; 3]{
+    loop{
+        let n : [f64; 3] = [rng.sample(StandardNormal), rng.sample(StandardNormal), rng.sample(StandardNormal)];
+        let norm = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        if norm > 0.0{
+            return [n[0] / norm, n[1] / norm, n[2] / norm];
+        }
+    }
+}
+
+/// Relax the length of every spin of `spins` over the time `delta_t`, with the fields of `haml_fn`
+/// evaluated at time t. Only the first `num_spins` lanes of each row are updated, so the padding
+/// lanes are left unchanged. The standard normal increment of each spin is drawn from
+/// `counter_rng(seed, step, r, c)` of its row r and chunk c, starting at a block that the rotation
+/// of the step does not use.
+///
+/// A spin of zero length has no direction along which its length could grow. It is given a
+/// uniformly random direction \hat{n}, and the step from r = 0 is taken with h \cdot \hat{n}, so that
+/// |m| = 0 is not absorbing. Without noise or field, such a spin stays at zero.
+pub fn llb_radial_update<Fh>(
+    spins: &mut Array2<Vector3d4xf64>,
+    num_spins: usize,
+    t: f64, delta_t: f64,
+    haml_fn: &Fh,
+    llb: &LlbParameters,
+    seed: u64, step: u32
+) where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync
+{
+    let n_ch = spins.shape()[1];
+    assert!(num_spins <= 4 * n_ch, "llb_radial_update: {} spins do not fit in {} chunks", num_spins, n_ch);
+    let noise_sd = (llb.b * delta_t).sqrt();
+
+    par_counter_rows(
+        spins.axis_iter_mut(Axis(0)).into_par_iter(),
+        || Array1::from_elem(n_ch, Vector3d4xf64::zeros()),
+        |row, h: &mut Array1<Vector3d4xf64>, mut m_row: ArrayViewMut1<Vector3d4xf64>|{
+            haml_fn(t, &m_row.view(), &mut h.view_mut());
+            for (c, (m, hc)) in m_row.iter_mut().zip(h.iter()).enumerate(){
+                let mut rng = counter_rng(seed, step, row, c);
+                rng.seek_block(RADIAL_BLOCK);
+                for l in 0..4.min(num_spins.saturating_sub(4 * c)){
+                    let xi : f64 = rng.sample(StandardNormal);
+                    let r = (m[0].dat[l].powi(2) + m[1].dat[l].powi(2) + m[2].dat[l].powi(2)).sqrt();
+                    let n = if r > 0.0{
+                        [m[0].dat[l] / r, m[1].dat[l] / r, m[2].dat[l] / r]
+                    } else {
+                        random_direction(&mut rng)
+                    };
+                    let h_par = hc[0].dat[l] * n[0] + hc[1].dat[l] * n[1] + hc[2].dat[l] * n[2];
+                    let r1 = (r + llb.drift(r, h_par) * delta_t + noise_sd * xi).abs();
+                    for a in 0..3{
+                        m[a].dat[l] = r1 * n[a];
+                    }
+                }
+            }
+            0.0
+        });
+}
+
+/// Peform a step of the Landau-Lifshitz-Bloch equation on `num_spins` spins: a rotation as in
+/// `spin_langevin_step_counter` with the transverse damping `eta` and noise strength `b`,
+/// followed by the longitudinal relaxation of `llb_radial_update` at the end of the step.
+/// Both draw their noise from the generators `counter_rng(seed, step, row, chunk)`.
+///
+/// Returns the average magnitude of the final propagator of each step.
+pub fn spin_langevin_step_llb<I, Fh, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64, b: f64,
+    llb: &LlbParameters,
+    num_spins: usize,
+    haml_fn: Fh,
+    seed: u64, step: u32,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> f64
+    where I: SpinIntegrator,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          Fr: Fn(&mut PhiloxRng) -> Vector3d4xf64 + Send + Sync
+{
+    let avg_om = spin_langevin_step_counter(spins_t0, spins_tf, t0, delta_t, eta, b, &haml_fn,
+                                            seed, step, &rand_xi_f, scheme);
+    llb_radial_update(spins_tf, num_spins, t0 + delta_t, delta_t, &haml_fn, llb, seed, step);
+
+    avg_om
+}
+
+#[cfg(test)]
+mod tests{
+    use ndarray::{Array2, ArrayView1, ArrayViewMut1};
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_distr::StandardNormal;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{curie_weiss_magnetization, llb_radial_update, spin_langevin_step_llb, LlbParameters};
+    use crate::philox::PhiloxRng;
+    use crate::MagnusScheme;
+
+    #[test]
+    fn test_llb_length_relaxation(){
+        let m_cw = curie_weiss_magnetization(0.5);
+        let x = 6.0 * m_cw;
+        assert!((m_cw - (1.0 / x.tanh() - 1.0 / x)).abs() < 1.0e-10);
+        assert_eq!(curie_weiss_magnetization(1.2), 0.0);
+
+        let rand_xi_f = |rng: &mut PhiloxRng| -> Vector3d4xf64{
+            let mut v = Vector3d4xf64::zeros();
+            for a in 0..3{
+                for x in v[a].dat.iter_mut(){
+                    *x = rng.sample(StandardNormal);
+                }
+            }
+            v
+        };
+        let haml_fn = |_t: f64, _m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            for hc in h.iter_mut(){
+                hc[0] = Aligned4xf64::from(0.0);
+                hc[1] = Aligned4xf64::from(0.0);
+                hc[2] = Aligned4xf64::from(0.0);
+            }
+        };
+        let (lambda, m_e, chi, kt) = (1.0, 0.8, 0.5, 0.01);
+        let llb = LlbParameters::thermal(lambda, m_e, chi, kt);
+        let mut m : Array2<Vector3d4xf64> = Array2::from_elem((64, 4), Zero::zero());
+        m.iter_mut().for_each(|mi| mi[2] = Aligned4xf64::from(1.0));
+        let mut mf = m.clone();
+        let dt = 0.01;
+        for k in 0..400{
+            spin_langevin_step_llb(&m, &mut mf, k as f64 * dt, dt, 0.0, 0.0, &llb, 16, haml_fn,
+                                   21, k, rand_xi_f, &MagnusScheme::Magnus2);
+            std::mem::swap(&mut m, &mut mf);
+        }
+
+        // Compare the mean length with the stationary density \exp(-F(r) / k_B T)
+        let f = |r: f64| (r * r - m_e * m_e).powi(2) / (8.0 * chi * m_e * m_e);
+        let (mut z, mut zr) = (0.0, 0.0);
+        for j in 0..4000{
+            let r = (j as f64 + 0.5) * 2.0e-3;
+            let p = (-f(r) / kt).exp();
+            z += p;
+            zr += p * r;
+        }
+        let mut r_mean = 0.0;
+        for mi in m.iter(){
+            let r2 = mi[0] * mi[0] + mi[1] * mi[1] + mi[2] * mi[2];
+            r_mean += r2.map(f64::sqrt).mean_reduce() / m.len() as f64;
+            // Only the length relaxes
+            assert!(mi[0].dat.iter().chain(mi[1].dat.iter()).all(|&x| x == 0.0));
+        }
+        // Var(r) ~ k_B T \chi_\parallel, so the mean over 1024 lanes has a standard error of ~2.2e-3
+        assert!((r_mean - zr / z).abs() < 1.2e-2, "{} != {}", r_mean, zr / z);
+
+        // Spins of zero length leave the origin, while the padding lane stays inert
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((2, 1), Zero::zero());
+        llb_radial_update(&mut m0, 3, 0.0, dt, &haml_fn, &llb, 22, 0);
+        for mi in m0.iter(){
+            let r2 = mi[0] * mi[0] + mi[1] * mi[1] + mi[2] * mi[2];
+            assert!(r2.dat[..3].iter().all(|&x| x > 0.0) && r2.dat[3] == 0.0, "{:?}", r2.dat);
+        }
+    }
+}
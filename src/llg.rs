@@ -0,0 +1,238 @@
+//! Landau-Lifshitz-Gilbert front-end in SI units
+//!
+//! The stochastic LLG equation of a magnetic cell with magnetization M = M_s m and volume V,
+//!     dm/dt = -\gamma m \cross (B + B_{th}) + \alpha m \cross dm/dt,       B = \mu_0 H_{eff}
+//! is equivalent to the Landau-Lifshitz form
+//!     dm/dt = -\gamma' m \cross B - \alpha \gamma' m \cross (m \cross B),      \gamma' = \gamma / (1 + \alpha^2)
+//! which is the Spin-Langevin equation
+//!     dm/dt = (h - \eta h \cross m) \cross m
+//! with \eta = \alpha, once the time is measured in units of t_0 = 1 / (\gamma' B_0) for a field
+//! scale B_0, and the field is h = B / B_0. The energy of the cell is E = -M_s V m \cdot B, so the
+//! dimensionless temperature is k_B T / (M_s V B_0), and the noise strength satisfies the
+//! fluctuation-dissipation relation
+//!     b = 2 \alpha k_B T / (M_s V B_0)
+//!
+//! `LlgUnits` performs these conversions, and `LlgUnits::step` propagates spins with a field
+//! closure in tesla and times in seconds.
+
+use std::sync::Mutex;
+
+use ndarray::{Array2, Array3, ArrayView1, ArrayViewMut1};
+use rand::Rng;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::{spin_langevin_step_scheme, unpack_xyz_rows, SpinIntegrator};
+
+/// Vacuum permeability \mu_0 (T m / A)
+pub const MU_0: f64 = 1.256_637_062_12e-6;
+/// Boltzmann constant (J / K)
+pub const K_B: f64 = 1.380_649e-23;
+/// Gyromagnetic ratio of the free electron (rad / (s T))
+pub const GAMMA_E: f64 = 1.760_859_630_23e11;
+
+/// Material and cell parameters of the LLG equation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LlgParameters{
+    /// Saturation magnetization M_s (A / m)
+    pub ms: f64,
+    /// Gyromagnetic ratio \gamma (rad / (s T))
+    pub gamma: f64,
+    /// Gilbert damping \alpha
+    pub alpha: f64,
+    /// Temperature (K)
+    pub temperature: f64,
+    /// Cell volume (m^3)
+    pub volume: f64
+}
+
+impl LlgParameters{
+    /// Parameters with the free electron gyromagnetic ratio
+    pub fn new(ms: f64, alpha: f64, temperature: f64, volume: f64) -> Self{
+        Self::with_gamma(ms, GAMMA_E, alpha, temperature, volume)
+    }
+
+    pub fn with_gamma(ms: f64, gamma: f64, alpha: f64, temperature: f64, volume: f64) -> Self{
+        assert!(ms > 0.0 && gamma > 0.0 && volume > 0.0, "LlgParameters: M_s, gamma and V must be positive");
+        assert!(alpha >= 0.0, "LlgParameters: negative damping");
+        assert!(temperature >= 0.0, "LlgParameters: negative temperature");
+        Self{ms, gamma, alpha, temperature, volume}
+    }
+
+    /// The reduced gyromagnetic ratio \gamma' = \gamma / (1 + \alpha^2)
+    pub fn gamma_prime(&self) -> f64{
+        self.gamma / (1.0 + self.alpha * self.alpha)
+    }
+}
+
+/// Conversion between SI units and the dimensionless Spin-Langevin equation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LlgUnits{
+    pub params: LlgParameters,
+    /// Field scale B_0 (T)
+    pub field_scale: f64
+}
+
+impl LlgUnits{
+    /// Units with the field scale B_0 = 1 T
+    pub fn new(params: LlgParameters) -> Self{
+        Self::with_field_scale(params, 1.0)
+    }
+
+    pub fn with_field_scale(params: LlgParameters, field_scale: f64) -> Self{
+        assert!(field_scale > 0.0, "LlgUnits: the field scale must be positive");
+        Self{params, field_scale}
+    }
+
+    /// The time unit t_0 = 1 / (\gamma' B_0) (s)
+    pub fn time_unit(&self) -> f64{
+        1.0 / (self.params.gamma_prime() * self.field_scale)
+    }
+
+    /// The dissipation strength \eta = \alpha
+    pub fn eta(&self) -> f64{
+        self.params.alpha
+    }
+
+    /// The noise strength b = 2 \alpha k_B T / (M_s V B_0)
+    pub fn b(&self) -> f64{
+        2.0 * self.params.alpha * self.kt()
+    }
+
+    /// The dimensionless temperature k_B T / (M_s V B_0)
+    pub fn kt(&self) -> f64{
+        K_B * self.params.temperature / self.energy_unit()
+    }
+
+    /// The energy unit M_s V B_0 (J)
+    pub fn energy_unit(&self) -> f64{
+        self.params.ms * self.params.volume * self.field_scale
+    }
+
+    pub fn time_to_si(&self, t: f64) -> f64{
+        t * self.time_unit()
+    }
+
+    pub fn time_from_si(&self, t_s: f64) -> f64{
+        t_s / self.time_unit()
+    }
+
+    /// Dimensionless field of a flux density B (T)
+    pub fn field_from_tesla(&self, b: f64) -> f64{
+        b / self.field_scale
+    }
+
+    /// Dimensionless field of a magnetic field H (A / m), B = \mu_0 H
+    pub fn field_from_ampere_per_meter(&self, h: f64) -> f64{
+        MU_0 * h / self.field_scale
+    }
+
+    pub fn field_to_tesla(&self, h: f64) -> f64{
+        h * self.field_scale
+    }
+
+    pub fn energy_to_si(&self, e: f64) -> f64{
+        e * self.energy_unit()
+    }
+
+    pub fn energy_from_si(&self, e_j: f64) -> f64{
+        e_j / self.energy_unit()
+    }
+
+    /// The magnetization M = M_s m (A / m) of the first n spins of every row, with the shape
+    /// (rows, n, 3)
+    pub fn magnetization(&self, spins: &Array2<Vector3d4xf64>, n: usize) -> Array3<f64>{
+        unpack_xyz_rows(spins.view(), n) * self.params.ms
+    }
+
+    /// Peform a step of the stochastic LLG equation from the time `t_s` to `t_s + delta_t_s`
+    /// (in seconds). The closure `field_fn` writes the effective fields B (in tesla, including
+    /// \mu_0) of the spins at a time in seconds, with the same conventions as the `haml_fn` of
+    /// `spin_langevin_step`. The thermal field is added internally.
+    ///
+    /// Returns the average magnitude of the final propagator of each step.
+    pub fn step<I, Fh, R, Fr>(
+        &self,
+        spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+        t_s: f64, delta_t_s: f64,
+        field_fn: Fh,
+        rng_arr: &Vec<Mutex<R>>,
+        rand_xi_f: Fr,
+        scheme: &I
+    ) -> f64
+        where I: SpinIntegrator,
+              Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+              R: Rng + Send + Sync,
+              Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        let t_unit = self.time_unit();
+        let inv_b0 = Aligned4xf64::from(1.0 / self.field_scale);
+        let haml_fn = |t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            field_fn(t * t_unit, m, h);
+            for hc in h.iter_mut(){
+                *hc *= inv_b0;
+            }
+        };
+
+        spin_langevin_step_scheme(spins_t0, spins_tf, t_s / t_unit, delta_t_s / t_unit, self.eta(), self.b(),
+                                  haml_fn, rng_arr, rand_xi_f, scheme)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Mutex;
+
+    use ndarray::{Array2, ArrayView1, ArrayViewMut1};
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{LlgParameters, LlgUnits, GAMMA_E, K_B};
+    use crate::MagnusScheme;
+
+    #[test]
+    fn test_llg_units(){
+        // Permalloy cell of 5 nm x 5 nm x 3 nm at room temperature
+        let params = LlgParameters::new(8.0e5, 0.02, 300.0, 7.5e-26);
+        let units = LlgUnits::with_field_scale(params, 0.1);
+        assert!((units.time_unit() * GAMMA_E * 0.1 - (1.0 + 0.02 * 0.02)).abs() < 1.0e-12);
+        let b = 2.0 * 0.02 * K_B * 300.0 / (8.0e5 * 7.5e-26 * 0.1);
+        assert!((units.b() / b - 1.0).abs() < 1.0e-12);
+        assert!((units.energy_to_si(units.kt()) - K_B * 300.0).abs() < 1.0e-30);
+        assert!((units.time_from_si(units.time_to_si(3.0)) - 3.0).abs() < 1.0e-12);
+
+        // Damped precession in a 50 mT field:  \tan(\theta / 2) = \exp(-\alpha \gamma' B t)
+        let params = LlgParameters::new(8.0e5, 0.1, 0.0, 7.5e-26);
+        let units = LlgUnits::new(params);
+        let field_fn = |_t: f64, _m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            for hc in h.iter_mut(){
+                hc[0] = Aligned4xf64::from(0.0);
+                hc[1] = Aligned4xf64::from(0.0);
+                hc[2] = Aligned4xf64::from(0.05);
+            }
+        };
+        let rng_arr : Vec<Mutex<Xoshiro256Plus>> = (0..rayon::current_num_threads())
+            .map(|k| Mutex::new(Xoshiro256Plus::seed_from_u64(k as u64))).collect();
+        let rand_xi_f = |_rng: &mut Xoshiro256Plus| Vector3d4xf64::zeros();
+        let mut m : Array2<Vector3d4xf64> = Array2::from_elem((1, 1), Zero::zero());
+        m[(0, 0)][0] = Aligned4xf64::from(1.0);
+        let mut mf = m.clone();
+        let dt = 2.0e-12;
+        for k in 0..200{
+            units.step(&m, &mut mf, k as f64 * dt, dt, field_fn, &rng_arr, rand_xi_f, &MagnusScheme::Magnus2);
+            std::mem::swap(&mut m, &mut mf);
+        }
+        let t = 200.0 * dt;
+        let mag = units.magnetization(&m, 1);
+        let theta = 2.0 * (-0.1 * params.gamma_prime() * 0.05 * t).exp().atan();
+        assert!((mag[(0, 0, 2)] / 8.0e5 - theta.cos()).abs() < 1.0e-4);
+        // Counterclockwise precession about B with the angular frequency \gamma' B
+        let phi = mag[(0, 0, 1)].atan2(mag[(0, 0, 0)]);
+        let phi_exact = params.gamma_prime() * 0.05 * t;
+        let dphi = (phi - phi_exact).rem_euclid(2.0 * std::f64::consts::PI);
+        assert!(dphi.min(2.0 * std::f64::consts::PI - dphi) < 1.0e-3);
+    }
+}
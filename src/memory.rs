@@ -0,0 +1,254 @@
+//! Generalized Spin-Langevin equation with memory kernel dissipation
+//!
+//!     dm/dt = ( h(t) - \int_0^t K(t - s) dm/ds ds + \xi(t) ) \cross m
+//!
+//! Following Jayannavar, the instantaneous dissipation -\eta h \cross m ~ -\eta dm/dt is replaced by
+//! a retarded friction with the memory kernel K, and the noise \xi is colored consistently with the
+//! fluctuation-dissipation theorem
+//!     < \xi_a(t) \xi_b(s) > = \delta_{ab} k_B T K(|t - s|).
+//!
+//! The kernel is a sum of exponentials
+//!     K(t) = \sum_k (c_k / \tau_k) e^{-t/\tau_k},
+//! which is embedded in Markovian dynamics by the auxiliary fields y_k = z_k + \xi_k with
+//!     dz_k = -z_k / \tau_k dt - (c_k / \tau_k) dm,
+//! and \xi_k an Ornstein-Uhlenbeck process of strength b_k = 2 k_B T c_k. The memory field \sum_k y_k
+//! is folded into the noise integrals consumed by the Magnus step, with the spin velocity dm/dt
+//! taken from the previous step. As \tau_k -> 0, the equation reduces to the Markovian
+//! Spin-Langevin equation with \eta = \sum_k c_k and b = 2 \eta k_B T, to first order in \eta.
+
+use std::sync::Mutex;
+
+use ndarray::{Array2, ArrayView1, ArrayViewMut1, Zip};
+use num_traits::Zero;
+use rand::Rng;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::noise::OuCoefs;
+use crate::{par_rng_fn_rows, spin_langevin_step_noise, SpinIntegrator, StepNorms};
+
+/// Exponential-sum memory kernel  K(t) = \sum_k (c_k / \tau_k) e^{-t/\tau_k}
+#[derive(Clone, Debug)]
+pub struct MemoryKernel{
+    modes: Vec<(f64, f64)>
+}
+
+impl MemoryKernel{
+    /// Kernel with the modes (c_k, \tau_k). A mode with \tau_k = 0 is instantaneous.
+    pub fn new(modes: &[(f64, f64)]) -> Self{
+        assert!(modes.iter().all(|&(c, tau)| c >= 0.0 && tau >= 0.0),
+                "MemoryKernel: mode strengths and times must be non-negative");
+        Self{modes: modes.to_vec()}
+    }
+
+    pub fn modes(&self) -> &[(f64, f64)]{
+        &self.modes
+    }
+
+    /// K(t) for t > 0, excluding instantaneous modes
+    pub fn eval(&self, t: f64) -> f64{
+        self.modes.iter().filter(|&&(_, tau)| tau > 0.0)
+            .map(|&(c, tau)| c / tau * (-t / tau).exp()).sum()
+    }
+
+    /// The Markovian damping  \eta = \int_0^\infty K(t) dt = \sum_k c_k
+    pub fn eta(&self) -> f64{
+        self.modes.iter().map(|&(c, _)| c).sum()
+    }
+}
+
+/// Auxiliary state of the memory kernel bath for every spin of every replica
+pub struct MemoryBath{
+    pub kernel: MemoryKernel,
+    pub kt: f64,
+    z: Vec<Array2<Vector3d4xf64>>,
+    xi: Vec<Array2<Vector3d4xf64>>,
+    v: Array2<Vector3d4xf64>,
+    z1: Array2<Vector3d4xf64>,
+    z2: Array2<Vector3d4xf64>,
+    noise1: Array2<Vector3d4xf64>,
+    noise2: Array2<Vector3d4xf64>
+}
+
+impl MemoryBath{
+    /// Bath for spin arrays of the shape `shape` at the temperature k_B T = `kt`, with the noise
+    /// drawn from its stationary distribution. `rand_xi_f` must return standard normal samples.
+    pub fn new<R, Fr>(shape: (usize, usize), kernel: MemoryKernel, kt: f64,
+                      rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr) -> Self
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!(kt >= 0.0, "MemoryBath: the temperature must be non-negative");
+        let zeros = || Array2::from_elem(shape, Zero::zero());
+        let xi = kernel.modes.iter().map(|&(c, tau)|{
+            let mut xi = zeros();
+            if tau > 0.0{
+                par_rng_fn_rows(&mut xi, rng_arr, Aligned4xf64::from((kt * c / tau).sqrt()), &rand_xi_f);
+            }
+            xi
+        }).collect();
+        let z = kernel.modes.iter().map(|_| zeros()).collect();
+
+        Self{kernel, kt, z, xi, v: zeros(), z1: zeros(), z2: zeros(), noise1: zeros(), noise2: zeros()}
+    }
+
+    /// The memory field  \sum_k y_k  at the start of the next step
+    pub fn field(&self) -> Array2<Vector3d4xf64>{
+        let mut y = Array2::from_elem(self.v.raw_dim(), Zero::zero());
+        for (z, xi) in self.z.iter().zip(self.xi.iter()){
+            Zip::from(&mut y).and(z).and(xi).apply(|y, &z, &xi| *y += z + xi);
+        }
+        y
+    }
+
+    /// The half-step integrals of the memory field of the last prepared step, in the form
+    /// consumed by `spin_langevin_step_noise`
+    pub fn noise(&self) -> (&Array2<Vector3d4xf64>, &Array2<Vector3d4xf64>){
+        (&self.noise1, &self.noise2)
+    }
+
+    /// Evaluate the half-step integrals of the memory field over a step of length `delta_t`,
+    /// with the friction part extrapolated from the previous spin velocity, and advance the
+    /// noise processes \xi_k
+    pub fn prepare<R, Fr>(&mut self, delta_t: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        let h = delta_t / 2.0;
+        let h_sqrt_inv = Aligned4xf64::from(1.0 / h.sqrt());
+        let one = Aligned4xf64::from(1.0);
+        self.noise1.fill(Zero::zero());
+        self.noise2.fill(Zero::zero());
+        for (k, &(c, tau)) in self.kernel.modes.iter().enumerate(){
+            // z(s) = z_0 e^{-s/\tau} - c v (1 - e^{-s/\tau})
+            let e = if tau > 0.0 { (-h / tau).exp() } else { 0.0 };
+            let tau_1me = tau * (1.0 - e);
+            let (za, zv) = (Aligned4xf64::from(tau_1me), Aligned4xf64::from(c * (tau_1me - h)));
+            let (e, cv) = (Aligned4xf64::from(e), Aligned4xf64::from(c * (1.0 - e)));
+            Zip::from(&mut self.noise1).and(&mut self.noise2).and(&self.z[k]).and(&self.v)
+                .par_apply(|n1, n2, &z0, &v|{
+                    *n1 += (z0 * za + v * zv) * h_sqrt_inv;
+                    let zh = z0 * e - v * cv;
+                    *n2 += (zh * za + v * zv) * h_sqrt_inv;
+                });
+
+            let coefs = OuCoefs::new(tau, 2.0 * self.kt * c, h);
+            let (e, cc, d) = (Aligned4xf64::from(coefs.e), Aligned4xf64::from(coefs.c), Aligned4xf64::from(coefs.d));
+            let (a, s) = (Aligned4xf64::from(coefs.a), Aligned4xf64::from(coefs.s));
+            for noise in [&mut self.noise1, &mut self.noise2].iter_mut(){
+                par_rng_fn_rows(&mut self.z1, rng_arr, one, &rand_xi_f);
+                par_rng_fn_rows(&mut self.z2, rng_arr, one, &rand_xi_f);
+                Zip::from(&mut **noise).and(&mut self.xi[k]).and(&self.z1).and(&self.z2)
+                    .par_apply(|chi, xi, &z1, &z2|{
+                        *chi += *xi * a + z1 * s;
+                        *xi = *xi * e + z1 * cc + z2 * d;
+                    });
+            }
+        }
+    }
+
+    /// Advance the friction fields z_k over the step from `spins_t0` to `spins_tf`
+    pub fn update(&mut self, spins_t0: &Array2<Vector3d4xf64>, spins_tf: &Array2<Vector3d4xf64>, delta_t: f64){
+        let dt_inv = Aligned4xf64::from(1.0 / delta_t);
+        Zip::from(&mut self.v).and(spins_t0).and(spins_tf)
+            .par_apply(|v, &m0, &mf| *v = (mf - m0) * dt_inv);
+        for (k, &(c, tau)) in self.kernel.modes.iter().enumerate(){
+            let e = if tau > 0.0 { (-delta_t / tau).exp() } else { 0.0 };
+            let (e, cv) = (Aligned4xf64::from(e), Aligned4xf64::from(c * (1.0 - e)));
+            Zip::from(&mut self.z[k]).and(&self.v)
+                .par_apply(|z, &v| *z = *z * e - v * cv);
+        }
+    }
+}
+
+/// Peform a step of the generalized Spin-Langevin equation with the memory kernel bath `bath`,
+/// using the integration scheme `scheme`. `eta` is an additional instantaneous damping, and is
+/// usually zero. `rand_xi_f` must return standard normal samples.
+pub fn spin_langevin_step_memory<I, Fh, R, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64,
+    haml_fn: Fh,
+    bath: &mut MemoryBath,
+    rng_arr: & Vec<Mutex<R>>,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> StepNorms
+    where I: SpinIntegrator,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+{
+    assert_eq!(bath.v.raw_dim(), spins_t0.raw_dim(), "spin_langevin_step_memory: mismatching bath shape");
+    bath.prepare(delta_t, rng_arr, rand_xi_f);
+    let norms = spin_langevin_step_noise(spins_t0, spins_tf, t0, delta_t, eta, 0.0, haml_fn,
+                                         &bath.noise1, &bath.noise2, scheme);
+    bath.update(spins_t0, spins_tf, delta_t);
+
+    norms
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Mutex;
+
+    use ndarray::Array2;
+    use rand::prelude::*;
+    use rand_distr::StandardNormal;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{spin_langevin_step_memory, MemoryBath, MemoryKernel};
+    use crate::MagnusScheme;
+
+    #[test]
+    fn test_memory_bath_equilibrium(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(13);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let rand_xi_f = |rng: &mut Xoshiro256Plus| -> Vector3d4xf64{
+            let mut v = Vector3d4xf64::zeros();
+            for a in 0..3{
+                for x in v[a].dat.iter_mut(){
+                    *x = rng.sample(StandardNormal);
+                }
+            }
+            v
+        };
+        let kernel = MemoryKernel::new(&[(0.3, 0.2), (0.2, 0.0)]);
+        assert!((kernel.eta() - 0.5).abs() < 1.0e-12);
+        assert!((kernel.eval(0.2) - 1.5 * (-1.0f64).exp()).abs() < 1.0e-12);
+
+        // Free spins in the field h = e_z relax to the Langevin function  < m_z > = coth(h/kT) - kT/h
+        let (kt, dt) = (0.5, 0.04);
+        let shape = (16, 4);
+        let mut bath = MemoryBath::new(shape, kernel, kt, &rng_arr, rand_xi_f);
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem(shape, Vector3d4xf64::zeros());
+        m0.iter_mut().for_each(|m| m[0] = Aligned4xf64::from(1.0));
+        let mut mf = m0.clone();
+        let haml_fn = |_t: f64, _m: &ndarray::ArrayView1<Vector3d4xf64>, h: &mut ndarray::ArrayViewMut1<Vector3d4xf64>|{
+            for hi in h.iter_mut(){
+                *hi = Vector3d4xf64::zeros();
+                hi[2] = Aligned4xf64::from(1.0);
+            }
+        };
+        let mut mz = 0.0;
+        let n_avg = 800;
+        for k in 0..1200{
+            spin_langevin_step_memory(&m0, &mut mf, k as f64 * dt, dt, 0.0, haml_fn,
+                                      &mut bath, &rng_arr, rand_xi_f, &MagnusScheme::Magnus2);
+            std::mem::swap(&mut m0, &mut mf);
+            if k >= 1200 - n_avg{
+                mz += m0.iter().map(|m| m[2].mean_reduce()).sum::<f64>() / (m0.len() * n_avg) as f64;
+            }
+        }
+        let x = 1.0 / kt;
+        let mz_eq = 1.0 / x.tanh() - 1.0 / x;
+        assert!((mz - mz_eq).abs() < 0.03, "<m_z> = {}, expected {}", mz, mz_eq);
+    }
+}
@@ -0,0 +1,508 @@
+//! Colored noise sources for the Spin-Langevin equation
+//!
+//! The white noise of `spin_langevin_step` is replaced by an Ornstein-Uhlenbeck process \xi(t)
+//!     d\xi = -\xi / \tau_c dt + \sqrt{b} / \tau_c dW
+//! with the correlation function  < \xi_a(t) \xi_b(s) > = \delta_{ab} (b / 2 \tau_c) e^{-|t-s|/\tau_c},
+//! so that \int < \xi(t) \xi(0) > dt = b as for the white noise \sqrt{b} dW/dt.
+//! The Spin-Langevin step consumes the integrals of \xi over each half step, which are sampled
+//! exactly together with \xi at the end of the half step, so the process is exact for any
+//! time step. As \tau_c -> 0 the integrals reduce to the white noise Brownian increments.
+//!
+//! The quantum thermostat noise instead has the symmetrized power spectrum of an ohmic bath
+//!     S(\omega) = \eta \omega \coth(\omega / 2 k_B T) e^{-|\omega|/\omega_c}      (\hbar \equiv 1)
+//! which reduces to the classical white noise strength b = 2 \eta k_B T for \omega << k_B T, \omega_c.
+//! It is generated by filtering white noise on the half step grid with a precomputed symmetric
+//! FIR filter whose response is \sqrt{S}. Normalized to b = 2 \eta k_B T, the filtered noise is
+//! also available as the `rand_xi_f` of `spin_langevin_step_counter`, where the white noise of
+//! every spin chunk and half step is regenerated from its Philox stream.
+
+use std::sync::Mutex;
+
+use ndarray::{Array2, ArrayView1, ArrayViewMut1, Zip};
+use num_traits::Zero;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::philox::PhiloxRng;
+use crate::{par_rng_fn_rows, spin_langevin_step_noise, SpinIntegrator, StepNorms};
+
+/// u^{-3}(u - 2(1 - e^{-u}) + (1 - e^{-2u})/2), the scaled variance of the integral of the
+/// OU process over a time u \tau_c
+fn ou_integral_var(u: f64) -> f64{
+    if u < 1.0e-2{
+        1.0/3.0 - u / 4.0 + 7.0 * u * u / 60.0
+    } else {
+        let e1 = -(-u).exp_m1();
+        let e2 = -(-2.0 * u).exp_m1();
+        (u - 2.0 * e1 + 0.5 * e2) / (u * u * u)
+    }
+}
+
+/// Coefficients of the exact update over a time h
+///     \xi' = e \xi + c z_1 + d z_2
+///     I / \sqrt{h} = a \xi + s z_1
+/// with z_1, z_2 independent standard normals
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct OuCoefs{
+    pub(crate) e: f64,
+    pub(crate) c: f64,
+    pub(crate) d: f64,
+    pub(crate) a: f64,
+    pub(crate) s: f64
+}
+
+impl OuCoefs{
+    pub(crate) fn new(tau_c: f64, b: f64, h: f64) -> Self{
+        if tau_c == 0.0{
+            return Self{e: 0.0, c: 0.0, d: 0.0, a: 0.0, s: b.sqrt()};
+        }
+        let u = h / tau_c;
+        let e = (-u).exp();
+        let one_m_e = -(-u).exp_m1();
+        // Var(I)/h, Cov(\xi', I)/\sqrt{h} and Var(\xi')
+        let var_i = b * u * u * ou_integral_var(u);
+        let cov = 0.5 * b * one_m_e * one_m_e / h.sqrt();
+        let var_x = 0.5 * b / tau_c * one_m_e * (1.0 + e);
+        let s = var_i.sqrt();
+        let c = if s > 0.0 { cov / s } else { 0.0 };
+        let d = (var_x - c * c).max(0.0).sqrt();
+
+        Self{e, c, d, a: tau_c * one_m_e / h.sqrt(), s}
+    }
+}
+
+/// Ornstein-Uhlenbeck noise with a persistent state for every spin of every replica
+pub struct OrnsteinUhlenbeckNoise{
+    pub tau_c: f64,
+    pub b: f64,
+    xi: Array2<Vector3d4xf64>,
+    z1: Array2<Vector3d4xf64>,
+    z2: Array2<Vector3d4xf64>,
+    noise1: Array2<Vector3d4xf64>,
+    noise2: Array2<Vector3d4xf64>
+}
+
+impl OrnsteinUhlenbeckNoise{
+    /// Noise for spin arrays of the shape `shape`, with \xi drawn from its stationary distribution.
+    /// `rand_xi_f` must return standard normal samples.
+    pub fn new<R, Fr>(shape: (usize, usize), tau_c: f64, b: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr) -> Self
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!(tau_c >= 0.0, "OrnsteinUhlenbeckNoise: the correlation time must be non-negative");
+        assert!(b >= 0.0, "Stochastic strength must be non-negative");
+        let mut xi = Array2::from_elem(shape, Zero::zero());
+        if tau_c > 0.0{
+            par_rng_fn_rows(&mut xi, rng_arr, Aligned4xf64::from((0.5 * b / tau_c).sqrt()), &rand_xi_f);
+        }
+        Self{tau_c, b, xi,
+             z1: Array2::from_elem(shape, Zero::zero()), z2: Array2::from_elem(shape, Zero::zero()),
+             noise1: Array2::from_elem(shape, Zero::zero()), noise2: Array2::from_elem(shape, Zero::zero())}
+    }
+
+    /// The current state \xi(t)
+    pub fn state(&self) -> &Array2<Vector3d4xf64>{
+        &self.xi
+    }
+
+    /// The scaled half-step integrals of the last sampled step, in the form consumed by
+    /// `spin_langevin_step_noise`
+    pub fn noise(&self) -> (&Array2<Vector3d4xf64>, &Array2<Vector3d4xf64>){
+        (&self.noise1, &self.noise2)
+    }
+
+    fn half_step<R, Fr>(&mut self, coefs: OuCoefs, second: bool, rng_arr: &Vec<Mutex<R>>, rand_xi_f: &Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        let one = Aligned4xf64::from(1.0);
+        par_rng_fn_rows(&mut self.z1, rng_arr, one, rand_xi_f);
+        par_rng_fn_rows(&mut self.z2, rng_arr, one, rand_xi_f);
+        let (e, c, d) = (Aligned4xf64::from(coefs.e), Aligned4xf64::from(coefs.c), Aligned4xf64::from(coefs.d));
+        let (a, s) = (Aligned4xf64::from(coefs.a), Aligned4xf64::from(coefs.s));
+        let noise = if second { &mut self.noise2 } else { &mut self.noise1 };
+        Zip::from(noise).and(&mut self.xi).and(&self.z1).and(&self.z2)
+            .par_apply(|chi, xi, &z1, &z2|{
+                *chi = *xi * a + z1 * s;
+                *xi = *xi * e + z1 * c + z2 * d;
+            });
+    }
+
+    /// Sample the noise integrals of a step of length `delta_t` and advance \xi by `delta_t`
+    pub fn sample<R, Fr>(&mut self, delta_t: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        let coefs = OuCoefs::new(self.tau_c, self.b, delta_t / 2.0);
+        self.half_step(coefs, false, rng_arr, &rand_xi_f);
+        self.half_step(coefs, true, rng_arr, &rand_xi_f);
+    }
+}
+
+/// A noise source with a persistent state, sampled once per step
+pub trait ColoredNoise{
+    /// Sample the noise integrals of a step of length `delta_t` and advance the state.
+    /// `rand_xi_f` must return standard normal samples.
+    fn sample<R, Fr>(&mut self, delta_t: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync;
+
+    /// The scaled half-step integrals of the last sampled step, in the form consumed by
+    /// `spin_langevin_step_noise`
+    fn noise(&self) -> (&Array2<Vector3d4xf64>, &Array2<Vector3d4xf64>);
+
+    /// The damping \eta the noise was generated for, if any, which must be the damping of the step
+    fn damping(&self) -> Option<f64>{
+        None
+    }
+}
+
+impl ColoredNoise for OrnsteinUhlenbeckNoise{
+    fn sample<R, Fr>(&mut self, delta_t: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        OrnsteinUhlenbeckNoise::sample(self, delta_t, rng_arr, rand_xi_f)
+    }
+
+    fn noise(&self) -> (&Array2<Vector3d4xf64>, &Array2<Vector3d4xf64>){
+        OrnsteinUhlenbeckNoise::noise(self)
+    }
+}
+
+/// The symmetrized quantum power spectrum of an ohmic bath with an exponential cutoff
+///     S(\omega) = \eta \omega \coth(\omega / 2 k_B T) e^{-|\omega|/\omega_c}
+pub fn ohmic_spectral_density(omega: f64, eta: f64, kt: f64, omega_c: f64) -> f64{
+    let w = omega.abs();
+    let bose = if kt == 0.0 {
+        w
+    } else if w < 1.0e-8 * kt {
+        2.0 * kt
+    } else {
+        w / (w / (2.0 * kt)).tanh()
+    };
+    eta * bose * (-w / omega_c).exp()
+}
+
+/// Quantum thermostat noise with the power spectrum `ohmic_spectral_density`
+pub struct QuantumThermostatNoise{
+    pub eta: f64,
+    pub kt: f64,
+    pub omega_c: f64,
+    delta_t: f64,
+    taps: Vec<f64>,
+    w: Vec<Array2<Vector3d4xf64>>,
+    head: usize,
+    noise1: Array2<Vector3d4xf64>,
+    noise2: Array2<Vector3d4xf64>
+}
+
+impl QuantumThermostatNoise{
+    /// Noise for spin arrays of the shape `shape` and the fixed step `delta_t`, filtered with
+    /// 2 `half_taps` + 1 coefficients. `rand_xi_f` must return standard normal samples.
+    pub fn new<R, Fr>(shape: (usize, usize), delta_t: f64, eta: f64, kt: f64, omega_c: f64, half_taps: usize,
+                      rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr) -> Self
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!(eta >= 0.0 && kt >= 0.0 && omega_c > 0.0, "QuantumThermostatNoise: invalid bath parameters");
+        assert!(delta_t > 0.0, "QuantumThermostatNoise: the time step must be positive");
+        // The half step sequence \chi_n has the power spectrum P(\theta) = S(\theta / h) on [-\pi, \pi]
+        // and is filtered by g_k = (1/\pi) \int_0^\pi \sqrt{P(\theta)} \cos(k \theta) d\theta
+        let h = delta_t / 2.0;
+        let n_quad = 4096.max(16 * half_taps);
+        let dtheta = std::f64::consts::PI / n_quad as f64;
+        let sqrt_p : Vec<f64> = (0..=n_quad)
+            .map(|j| ohmic_spectral_density(j as f64 * dtheta / h, eta, kt, omega_c).sqrt())
+            .collect();
+        let g : Vec<f64> = (0..=half_taps).map(|k|{
+            let f = |j: usize| sqrt_p[j] * (k as f64 * j as f64 * dtheta).cos();
+            let inner : f64 = (1..n_quad).map(f).sum();
+            (inner + 0.5 * (f(0) + f(n_quad))) * dtheta / std::f64::consts::PI
+        }).collect();
+        let taps : Vec<f64> = g.iter().rev().chain(g.iter().skip(1)).cloned().collect();
+
+        let one = Aligned4xf64::from(1.0);
+        let w = (0..taps.len()).map(|_|{
+            let mut w = Array2::from_elem(shape, Zero::zero());
+            par_rng_fn_rows(&mut w, rng_arr, one, &rand_xi_f);
+            w
+        }).collect();
+
+        Self{eta, kt, omega_c, delta_t, taps, w, head: 0,
+             noise1: Array2::from_elem(shape, Zero::zero()), noise2: Array2::from_elem(shape, Zero::zero())}
+    }
+
+    /// The symmetric filter coefficients g_{-K} ... g_K
+    pub fn taps(&self) -> &[f64]{
+        &self.taps
+    }
+
+    /// The noise as the `rand_xi_f` of `spin_langevin_step_counter`, normalized to the classical
+    /// strength b = 2 \eta k_B T. With the time step of the filter and b = 2 \eta k_B T at the
+    /// temperature of the noise, the driver restores the power spectrum S with the \eta of the step.
+    ///
+    /// The generator of a chunk only identifies the chunk, row and step of the sample, and its
+    /// block index whether the sample is \chi_1 or \chi_2. The white noise of half step n is
+    /// drawn from the Philox stream of (chunk, row, n), so the filter needs no state.
+    pub fn counter_xi_f(&self) -> impl Fn(&mut PhiloxRng) -> Vector3d4xf64 + Send + Sync{
+        assert!(self.eta > 0.0 && self.kt > 0.0,
+                "QuantumThermostatNoise: the normalized noise requires a positive damping and temperature");
+        let norm = (2.0 * self.eta * self.kt).sqrt();
+        let taps : Vec<Aligned4xf64> = self.taps.iter().map(|g| Aligned4xf64::from(g / norm)).collect();
+        let half_taps = (taps.len() / 2) as u32;
+        move |rng: &mut PhiloxRng|{
+            let j = rng.block();
+            assert!(j < 2, "QuantumThermostatNoise: at most two samples per chunk and step");
+            rng.seek_block(j + 1);
+            let [chunk, row, step] = rng.stream();
+            let n = step.wrapping_mul(2).wrapping_add(j).wrapping_sub(half_taps);
+            let mut xi = Vector3d4xf64::zeros();
+            for (l, &g) in taps.iter().enumerate(){
+                let mut w = PhiloxRng::new(rng.seed(), [chunk, row, n.wrapping_add(l as u32)]);
+                for a in 0..3{
+                    for (x, wx) in xi[a].dat.iter_mut().zip(g.dat.iter()){
+                        *x += wx * w.sample::<f64, _>(StandardNormal);
+                    }
+                }
+            }
+            xi
+        }
+    }
+
+    fn half_step<R, Fr>(&mut self, second: bool, rng_arr: &Vec<Mutex<R>>, rand_xi_f: &Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        // Replace the oldest white noise sample and filter
+        par_rng_fn_rows(&mut self.w[self.head], rng_arr, Aligned4xf64::from(1.0), rand_xi_f);
+        self.head = (self.head + 1) % self.w.len();
+        let noise = if second { &mut self.noise2 } else { &mut self.noise1 };
+        noise.fill(Zero::zero());
+        let l = self.w.len();
+        for (k, &g) in self.taps.iter().enumerate(){
+            let g = Aligned4xf64::from(g);
+            Zip::from(&mut *noise).and(&self.w[(self.head + k) % l])
+                .par_apply(|chi, &w| *chi += w * g);
+        }
+    }
+}
+
+impl ColoredNoise for QuantumThermostatNoise{
+    fn sample<R, Fr>(&mut self, delta_t: f64, rng_arr: &Vec<Mutex<R>>, rand_xi_f: Fr)
+    where R: Rng + Send + Sync,
+          Fr: Fn(&mut R) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!((delta_t - self.delta_t).abs() <= 1.0e-12 * self.delta_t,
+                "QuantumThermostatNoise: the filter was computed for a different time step");
+        self.half_step(false, rng_arr, &rand_xi_f);
+        self.half_step(true, rng_arr, &rand_xi_f);
+    }
+
+    fn noise(&self) -> (&Array2<Vector3d4xf64>, &Array2<Vector3d4xf64>){
+        (&self.noise1, &self.noise2)
+    }
+
+    fn damping(&self) -> Option<f64>{
+        Some(self.eta)
+    }
+}
+
+/// Peform a step of the Spin-Langevin equation driven by the colored noise `noise`, using
+/// the integration scheme `scheme`. `rand_xi_f` must return standard normal samples.
+pub fn spin_langevin_step_colored<I, N, Fh, R, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64,
+    haml_fn: Fh,
+    noise: &mut N,
+    rng_arr: & Vec<Mutex<R>>,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> StepNorms
+    where I: SpinIntegrator,
+          N: ColoredNoise,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+{
+    if let Some(noise_eta) = noise.damping(){
+        assert!((noise_eta - eta).abs() <= 1.0e-12 * eta.abs(),
+                "spin_langevin_step_colored: the noise was generated for a different damping");
+    }
+    noise.sample(delta_t, rng_arr, rand_xi_f);
+    let (noise1, noise2) = noise.noise();
+    assert_eq!(noise1.raw_dim(), spins_t0.raw_dim(), "spin_langevin_step_colored: mismatching noise shape");
+    spin_langevin_step_noise(spins_t0, spins_tf, t0, delta_t, eta, 0.0, haml_fn, noise1, noise2, scheme)
+}
+
+/// Peform a step of the Spin-Langevin equation driven by the Ornstein-Uhlenbeck noise `ou`, using
+/// the integration scheme `scheme`. The noise strength b is that of `ou`.
+/// `rand_xi_f` must return standard normal samples.
+pub fn spin_langevin_step_ou<I, Fh, R, Fr>(
+    spins_t0: &Array2<Vector3d4xf64>, spins_tf: &mut Array2<Vector3d4xf64>,
+    t0: f64, delta_t : f64,
+    eta: f64,
+    haml_fn: Fh,
+    ou: &mut OrnsteinUhlenbeckNoise,
+    rng_arr: & Vec<Mutex<R>>,
+    rand_xi_f: Fr,
+    scheme: &I
+) -> StepNorms
+    where I: SpinIntegrator,
+          Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          R: Rng + Send + Sync,
+          Fr: Fn(& mut R) -> Vector3d4xf64 + Send + Sync
+{
+    spin_langevin_step_colored(spins_t0, spins_tf, t0, delta_t, eta, haml_fn, ou, rng_arr, rand_xi_f, scheme)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Mutex;
+
+    use ndarray::Array2;
+    use rand::prelude::*;
+    use rand_distr::StandardNormal;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{ohmic_spectral_density, ou_integral_var, spin_langevin_step_ou,
+                ColoredNoise, OrnsteinUhlenbeckNoise, QuantumThermostatNoise};
+    use crate::philox::{counter_rng, spin_langevin_step_counter};
+    use crate::MagnusScheme;
+
+    fn second_moment(arr: &Array2<Vector3d4xf64>) -> f64{
+        let s : f64 = arr.iter().map(|v| (v[0]*v[0] + v[1]*v[1] + v[2]*v[2]).mean_reduce()).sum();
+        s / (3 * arr.len()) as f64
+    }
+
+    #[test]
+    fn test_ou_noise_statistics(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(5);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let rand_xi_f = |rng: &mut Xoshiro256Plus| -> Vector3d4xf64{
+            let mut v = Vector3d4xf64::zeros();
+            for a in 0..3{
+                for x in v[a].dat.iter_mut(){
+                    *x = rng.sample(StandardNormal);
+                }
+            }
+            v
+        };
+        let (b, dt) = (0.3, 0.1);
+
+        // White noise limit
+        let mut white = OrnsteinUhlenbeckNoise::new((64, 16), 0.0, b, &rng_arr, rand_xi_f);
+        white.sample(dt, &rng_arr, rand_xi_f);
+        assert!((second_moment(white.noise().0) / b - 1.0).abs() < 0.05);
+        let mut short = OrnsteinUhlenbeckNoise::new((64, 16), 1.0e-6, b, &rng_arr, rand_xi_f);
+        short.sample(dt, &rng_arr, rand_xi_f);
+        assert!((second_moment(short.noise().1) / b - 1.0).abs() < 0.05);
+
+        // Stationary variances of \xi and of its half step integrals
+        let tau_c = 0.2;
+        let mut ou = OrnsteinUhlenbeckNoise::new((64, 16), tau_c, b, &rng_arr, rand_xi_f);
+        let u = 0.5 * dt / tau_c;
+        let var_chi = b * (u - 1.0 + (-u).exp()) / u;
+        let (mut m_xi, mut m_chi) = (0.0, 0.0);
+        for _ in 0..20{
+            ou.sample(dt, &rng_arr, rand_xi_f);
+            m_xi += second_moment(ou.state()) / 20.0;
+            m_chi += second_moment(ou.noise().0) / 20.0;
+        }
+        assert!((m_xi / (0.5 * b / tau_c) - 1.0).abs() < 0.05);
+        assert!((m_chi / var_chi - 1.0).abs() < 0.05);
+        assert!((ou_integral_var(0.00999) - ou_integral_var(0.01)).abs() < 1.0e-5);
+
+        // Colored noise driven steps preserve the spin norms
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((64, 16), Vector3d4xf64::zeros());
+        m0.iter_mut().for_each(|m| m[2] = Aligned4xf64::from(1.0));
+        let mut mf = m0.clone();
+        for k in 0..10{
+            spin_langevin_step_ou(&m0, &mut mf, k as f64 * dt, dt, 0.1, |_t, _m, h| h.fill(Vector3d4xf64::zeros()),
+                                  &mut ou, &rng_arr, rand_xi_f, &MagnusScheme::Magnus2);
+            std::mem::swap(&mut m0, &mut mf);
+        }
+        assert!((second_moment(&m0) * 3.0 - 1.0).abs() < 1.0e-10);
+    }
+
+    #[test]
+    fn test_quantum_thermostat_spectrum(){
+        let num_threads = rayon::current_num_threads();
+        let mut rng = Xoshiro256Plus::seed_from_u64(17);
+        let mut rng_arr = Vec::new();
+        for _ in 0..num_threads{
+            rng.jump();
+            rng_arr.push(Mutex::new(rng.clone()));
+        }
+        let rand_xi_f = |rng: &mut Xoshiro256Plus| -> Vector3d4xf64{
+            let mut v = Vector3d4xf64::zeros();
+            for a in 0..3{
+                for x in v[a].dat.iter_mut(){
+                    *x = rng.sample(StandardNormal);
+                }
+            }
+            v
+        };
+        let (eta, kt, omega_c, dt) = (0.1, 0.05, 10.0, 0.1);
+        // Classical limit and zero point fluctuations
+        assert!((ohmic_spectral_density(1.0e-3, eta, 10.0, omega_c) / (2.0 * eta * 10.0) - 1.0).abs() < 1.0e-3);
+        assert!((ohmic_spectral_density(2.0, eta, 0.0, omega_c) - 0.2 * (-0.2f64).exp()).abs() < 1.0e-12);
+
+        let mut noise = QuantumThermostatNoise::new((64, 16), dt, eta, kt, omega_c, 64, &rng_arr, rand_xi_f);
+        // The variance of \chi is the integral of the power spectrum over the Nyquist band
+        let h = dt / 2.0;
+        let n = 10000;
+        let var_p : f64 = (0..n).map(|j|{
+            let theta = (j as f64 + 0.5) * std::f64::consts::PI / n as f64;
+            ohmic_spectral_density(theta / h, eta, kt, omega_c)
+        }).sum::<f64>() / n as f64;
+        let var_g : f64 = noise.taps().iter().map(|g| g * g).sum();
+        assert!((var_g / var_p - 1.0).abs() < 0.01);
+        let mut m = 0.0;
+        for _ in 0..10{
+            noise.sample(dt, &rng_arr, rand_xi_f);
+            m += second_moment(noise.noise().0) / 10.0;
+        }
+        assert!((m / var_p - 1.0).abs() < 0.05);
+        // Zero point fluctuations exceed the classical white noise 2 \eta k_B T at low temperature
+        assert!(var_p > 2.0 * eta * kt);
+
+        // The rand_xi_f form scaled by b = 2 \eta k_B T has the same variance, and consecutive half
+        // steps of a spin are correlated by \sum_k g_k g_{k+1}
+        let xi_f = noise.counter_xi_f();
+        let b = 2.0 * eta * kt;
+        let corr_g : f64 = noise.taps().windows(2).map(|g| g[0] * g[1]).sum();
+        let (mut var, mut corr) = (0.0, 0.0);
+        for row in 0..64{
+            let xi : Vec<Vector3d4xf64> = (0..10).flat_map(|step|{
+                let mut rng = counter_rng(3, step, row, 0);
+                vec![xi_f(&mut rng), xi_f(&mut rng)]
+            }).collect();
+            for (x0, x1) in xi.iter().zip(xi.iter().skip(1)){
+                var += b * x0.dot(x0).mean_reduce() / 3.0 / (64 * 19) as f64;
+                corr += b * x0.dot(x1).mean_reduce() / 3.0 / (64 * 19) as f64;
+            }
+        }
+        assert!((var / var_p - 1.0).abs() < 0.05, "{} != {}", var, var_p);
+        assert!((corr - corr_g).abs() < 0.05 * var_p, "{} != {}", corr, corr_g);
+
+        let mut m0 : Array2<Vector3d4xf64> = Array2::from_elem((4, 2), Vector3d4xf64::zeros());
+        m0.iter_mut().for_each(|m| m[2] = Aligned4xf64::from(1.0));
+        let mut mf = m0.clone();
+        spin_langevin_step_counter(&m0, &mut mf, 0.0, dt, eta, b, |_t, _m, h| h.fill(Vector3d4xf64::zeros()),
+                                   5, 0, &xi_f, &MagnusScheme::Magnus2);
+        assert!(mf.iter().all(|m| (m.dot(m).mean_reduce() - 1.0).abs() < 1.0e-10));
+        assert_ne!(mf[(0, 0)][2].dat, m0[(0, 0)][2].dat);
+    }
+}
@@ -0,0 +1,253 @@
+//! Instances with planted, certified ground states
+//!
+//! Each generator returns a `PlantedInstance`: an Ising problem with zero local fields, together
+//! with a ground state s^* and its energy, so that the success probability of an anneal follows
+//! from `Readout::ground_state_probability` without an exact solver. The ground states are
+//! certified by construction: the energy is a sum of terms that are all minimized by s^*.
+//!
+//!  - Frustrated loops: a sum of loop Hamiltonians on cycles of an arbitrary graph, each with a
+//!    single frustrated bond (Hen et al., Phys. Rev. A 92, 042325 (2015)).
+//!  - Wishart planting: J = W W^T / N with the columns of W orthogonal to s^*
+//!    (Hamze et al., Phys. Rev. E 101, 052102 (2020)).
+//!  - Tile planting: 2D plaquettes with a single frustrated bond each, tiling the square lattice
+//!    in a checkerboard pattern (Hamze et al., Phys. Rev. E 97, 043303 (2018)).
+//!
+//! The instances are gauge transformed by a uniformly random s^*, and with E = \sum J_{ij} s_i s_j
+//! a bond is satisfied by s^* when J_{ij} s^*_i s^*_j < 0.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+use rand_distr::StandardNormal;
+
+use crate::graphs::Graph;
+use crate::ising::IsingProblem;
+
+/// An Ising problem with a known ground state
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlantedInstance{
+    pub problem: IsingProblem,
+    pub ground_state: Vec<i8>,
+    pub ground_energy: f64
+}
+
+impl PlantedInstance{
+    fn new(problem: IsingProblem, ground_state: Vec<i8>) -> Self{
+        let ground_energy = problem.energy(&ground_state);
+        Self{problem, ground_state, ground_energy}
+    }
+}
+
+fn random_spins<R: Rng + ?Sized>(n: usize, rng: &mut R) -> Vec<i8>{
+    (0..n).map(|_| if rng.gen::<bool>() { 1 } else { -1 }).collect()
+}
+
+/// Adjacency lists of the 2-core of a graph, obtained by repeatedly removing the nodes of
+/// degree at most 1. Every cycle of the graph lies in its 2-core, where every node has degree >= 2.
+fn two_core(graph: &Graph) -> Vec<Vec<usize>>{
+    let mut adj = graph.neighbors();
+    let mut leaves : Vec<usize> = (0..adj.len()).filter(|&v| adj[v].len() == 1).collect();
+    while let Some(v) = leaves.pop(){
+        for u in std::mem::take(&mut adj[v]){
+            adj[u].retain(|&w| w != v);
+            if adj[u].len() == 1{
+                leaves.push(u);
+            }
+        }
+    }
+    adj
+}
+
+/// A cycle found by a non-backtracking random walk from a random node of `starts`. On a 2-core
+/// every node has a neighbor to continue to, so the walk always closes a cycle.
+fn random_loop<R: Rng + ?Sized>(adj: &[Vec<usize>], starts: &[usize], rng: &mut R) -> Vec<usize>{
+    let mut path = vec![*starts.choose(rng).unwrap()];
+    let mut visited = HashMap::new();
+    visited.insert(path[0], 0);
+    loop{
+        let v = path[path.len() - 1];
+        let prev = if path.len() > 1 { Some(path[path.len() - 2]) } else { None };
+        let next = **adj[v].iter().filter(|&&u| Some(u) != prev)
+            .collect::<Vec<_>>().choose(rng)
+            .expect("random_loop: the walk left the 2-core");
+        if let Some(&p) = visited.get(&next){
+            return path.split_off(p);
+        }
+        visited.insert(next, path.len());
+        path.push(next);
+    }
+}
+
+/// Frustrated loop instance on `graph` with alpha N loops, where N is the number of nodes.
+/// Every loop of length L adds J = -1 to L - 1 of its bonds and J = +1 to one random bond,
+/// in the gauge of s^*, and loops are rejected if they would raise any |J_{ij}| above
+/// `max_coupling`. Loops shorter than `min_length` are rejected as well.
+///
+/// The loops are random walks on the 2-core of the graph, so trees and isolated nodes, such as
+/// the Pegasus qubits outside the fabric, are never visited. Returns None if the graph has no
+/// cycle, or if the loops are rejected too often, e.g. when no cycle is as long as `min_length`.
+pub fn frustrated_loops<R: Rng + ?Sized>(
+    graph: &Graph, alpha: f64, max_coupling: f64, min_length: usize, rng: &mut R
+) -> Option<PlantedInstance>{
+    assert!(alpha > 0.0 && max_coupling >= 1.0, "frustrated_loops: invalid loop density or coupling range");
+    let n = graph.num_nodes;
+    let adj = two_core(graph);
+    let starts : Vec<usize> = (0..n).filter(|&v| !adj[v].is_empty()).collect();
+    if starts.is_empty(){
+        return None;
+    }
+    let edge_idx : HashMap<(usize, usize), usize> = graph.edges.iter().enumerate()
+        .map(|(k, &e)| (e, k)).collect();
+    let num_loops = (alpha * n as f64).round() as usize;
+    let s = random_spins(n, rng);
+
+    let mut j = vec![0.0; graph.num_edges()];
+    let (mut accepted, mut rejected) = (0, 0);
+    while accepted < num_loops{
+        if rejected >= 1000 * (num_loops + 1){
+            return None;
+        }
+        let cycle = random_loop(&adj, &starts, rng);
+        if cycle.len() < min_length.max(3){
+            rejected += 1;
+            continue;
+        }
+        let frustrated = rng.gen_range(0, cycle.len());
+        let bonds : Vec<(usize, f64)> = (0..cycle.len()).map(|k|{
+            let (a, b) = (cycle[k], cycle[(k + 1) % cycle.len()]);
+            let sign = if k == frustrated { 1.0 } else { -1.0 };
+            (edge_idx[&(a.min(b), a.max(b))], sign * (s[a] * s[b]) as f64)
+        }).collect();
+        if bonds.iter().any(|&(e, dj)| (j[e] + dj).abs() > max_coupling){
+            rejected += 1;
+            continue;
+        }
+        for (e, dj) in bonds{
+            j[e] += dj;
+        }
+        accepted += 1;
+    }
+
+    let couplings = graph.edges.iter().zip(j.iter())
+        .filter(|(_, &jij)| jij != 0.0)
+        .map(|(&(a, b), &jij)| (a, b, jij)).collect();
+    Some(PlantedInstance::new(IsingProblem::new(vec![0.0; n], couplings), s))
+}
+
+/// Wishart planted instance of n spins with m = alpha n Gaussian columns w_\mu, projected
+/// orthogonal to s^*. With J = W W^T / N the energy is
+///     E(s) = (1/2) (s^T J s - tr J) >= -(1/2) tr J = E(s^*)
+/// The instances are hardest around alpha ~ 1 and are fully connected.
+pub fn wishart<R: Rng + ?Sized>(n: usize, alpha: f64, rng: &mut R) -> PlantedInstance{
+    let m = ((alpha * n as f64).round() as usize).max(1);
+    assert!(n >= 2, "wishart: at least two spins are required");
+    let s = random_spins(n, rng);
+    // Column-major W, n x m
+    let mut w = vec![0.0; n * m];
+    for col in w.chunks_mut(n){
+        for x in col.iter_mut(){
+            *x = rng.sample(StandardNormal);
+        }
+        let proj = col.iter().zip(s.iter()).map(|(&x, &si)| x * si as f64).sum::<f64>() / n as f64;
+        for (x, &si) in col.iter_mut().zip(s.iter()){
+            *x -= proj * si as f64;
+        }
+    }
+    let mut couplings = Vec::with_capacity(n * (n - 1) / 2);
+    for i in 0..n{
+        for k in i + 1..n{
+            let jik : f64 = w.chunks(n).map(|col| col[i] * col[k]).sum();
+            couplings.push((i, k, jik / n as f64));
+        }
+    }
+    PlantedInstance::new(IsingProblem::new(vec![0.0; n], couplings), s)
+}
+
+/// Weights of the satisfied bonds of the tile classes C_1, C_2 and C_3 used here. The fourth bond of a tile
+/// is frustrated with the weight 1, which does not exceed any satisfied weight, so the tile is
+/// minimized by s^* with exactly one broken bond.
+const TILE_CLASSES : [[f64; 3]; 3] = [[1.0, 1.0, 1.0], [1.0, 1.0, 2.0], [1.0, 2.0, 2.0]];
+
+/// Tile planted instance on the periodic l x l square lattice, l even, with site (x, y) at x + l y.
+/// The plaquettes with the lower left corner (x, y), x + y even, cover every bond exactly once.
+/// Each is drawn from the tile classes C_1, C_2, C_3 with the probabilities `p`, in a random
+/// orientation.
+pub fn tile_planted<R: Rng + ?Sized>(l: usize, p: [f64; 3], rng: &mut R) -> PlantedInstance{
+    assert!(l >= 4 && l % 2 == 0, "tile_planted: the linear size must be even and at least 4");
+    assert!(p.iter().all(|&pk| pk >= 0.0) && (p.iter().sum::<f64>() - 1.0).abs() < 1.0e-12,
+            "tile_planted: invalid class probabilities");
+    let n = l * l;
+    let s = random_spins(n, rng);
+    let idx = |x: usize, y: usize| (x % l) + l * (y % l);
+
+    let mut couplings = Vec::with_capacity(2 * n);
+    for y in 0..l{
+        for x in (0..l).filter(|&x| (x + y) % 2 == 0){
+            let u : f64 = rng.gen();
+            let class = if u < p[0] { 0 } else if u < p[0] + p[1] { 1 } else { 2 };
+            let mut weights = [-1.0, TILE_CLASSES[class][0], TILE_CLASSES[class][1], TILE_CLASSES[class][2]];
+            weights.shuffle(rng);
+            let corners = [idx(x, y), idx(x + 1, y), idx(x + 1, y + 1), idx(x, y + 1)];
+            for k in 0..4{
+                let (a, b) = (corners[k], corners[(k + 1) % 4]);
+                couplings.push((a.min(b), a.max(b), -weights[k] * (s[a] * s[b]) as f64));
+            }
+        }
+    }
+    couplings.sort_by_key(|c| (c.0, c.1));
+    PlantedInstance::new(IsingProblem::new(vec![0.0; n], couplings), s)
+}
+
+#[cfg(test)]
+mod tests{
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    use super::{frustrated_loops, tile_planted, wishart, PlantedInstance};
+    use crate::graphs::Graph;
+
+    /// Exhaustive minimum energy
+    fn brute_force_minimum(instance: &PlantedInstance) -> f64{
+        let n = instance.problem.num_spins();
+        (0..1u32 << n).map(|c|{
+            let s : Vec<i8> = (0..n).map(|i| if (c >> i) & 1 == 1 { 1 } else { -1 }).collect();
+            instance.problem.energy(&s)
+        }).fold(std::f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn test_planted_ground_states(){
+        let mut rng = Xoshiro256Plus::seed_from_u64(17);
+        let instances = vec![
+            frustrated_loops(&Graph::square(4, 4), 0.5, 3.0, 4, &mut rng).unwrap(),
+            frustrated_loops(&Graph::chimera(1, 2, 4), 1.0, 2.0, 4, &mut rng).unwrap(),
+            wishart(12, 0.75, &mut rng),
+            tile_planted(4, [0.2, 0.4, 0.4], &mut rng)
+        ];
+        for inst in instances.iter(){
+            assert!(!inst.problem.couplings.is_empty());
+            assert_eq!(inst.problem.energy(&inst.ground_state), inst.ground_energy);
+            let e_min = brute_force_minimum(inst);
+            assert!((e_min - inst.ground_energy).abs() < 1.0e-10, "{} != {}", e_min, inst.ground_energy);
+        }
+        // Loops respect the coupling range
+        assert!(instances[0].problem.couplings.iter().all(|c| c.2.abs() <= 3.0));
+        // Every tile has exactly one broken bond
+        let tiles = &instances[3];
+        let broken = tiles.problem.couplings.iter()
+            .filter(|&&(i, j, jij)| jij * (tiles.ground_state[i] * tiles.ground_state[j]) as f64 > 0.0).count();
+        assert_eq!(broken, 8);
+
+        // Loops avoid dead ends and isolated nodes, and graphs without long enough cycles are declined
+        let lollipop = Graph::from_edges(7, vec![(0, 1), (1, 2), (2, 3), (3, 0), (3, 4), (4, 5)]);
+        let inst = frustrated_loops(&lollipop, 1.0, 10.0, 4, &mut rng).unwrap();
+        assert!(inst.problem.couplings.iter().all(|c| c.1 <= 3));
+        assert_eq!(inst.ground_energy, -2.0 * 7.0);
+        let tree = Graph::from_edges(4, vec![(0, 1), (1, 2), (1, 3)]);
+        assert!(frustrated_loops(&tree, 1.0, 2.0, 3, &mut rng).is_none());
+        assert!(frustrated_loops(&Graph::square(4, 4), 0.5, 3.0, 17, &mut rng).is_none());
+        let p2 = Graph::pegasus(2);
+        assert!(frustrated_loops(&p2, 0.5, 2.0, 4, &mut rng).is_some());
+    }
+}
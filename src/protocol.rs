@@ -0,0 +1,187 @@
+//! Annealing protocols driven by `spin_langevin_step`
+//!
+//! A protocol runs an `AnnealingHamiltonian` along its annealing path s(t), from the first to
+//! the last breakpoint. Reverse and paused anneals start from classical Ising configurations,
+//! which are loaded as spins aligned to \pm z on every replica, i.e. every row of the spin array.
+//!
+//! The damping \eta and noise strength b are those of the Spin-Langevin equation, so a
+//! single-qubit coupling should already be rescaled by `hamiltonian::annealing::spin_eta`.
+//! The bath couples isotropically unless the protocol selects `BathCoupling::Dephasing`.
+//! Step k of a run draws its noise from `philox::counter_rng(seed, k, row, chunk)`, so a run is
+//! reproduced by its seed for any number of threads.
+
+use ndarray::Array2;
+use num_traits::Zero;
+use simd_phys::r3::Vector3d4xf64;
+
+use crate::bath::{spin_langevin_step_bath, BathCoupling, DephasingBath};
+use crate::hamiltonian::AnnealingHamiltonian;
+use crate::philox::{spin_langevin_step_counter, PhiloxRng};
+use crate::readout::z_sign;
+use crate::{num_chunks, MagnusScheme, SpinIntegrator};
+
+/// Spin array with `replicas` rows per Ising configuration, aligned to m_z = s_i.
+/// Row r holds a replica of configuration r / replicas.
+pub fn ising_to_spins(configs: &[Vec<i8>], replicas: usize) -> Array2<Vector3d4xf64>{
+    assert!(!configs.is_empty(), "ising_to_spins: no configurations");
+    let n = configs[0].len();
+    assert!(configs.iter().all(|c| c.len() == n), "ising_to_spins: mismatching configuration lengths");
+    let n_ch = num_chunks(n);
+    let mut spins = Array2::from_elem((configs.len() * replicas, n_ch), Vector3d4xf64::zero());
+    for (mut row, config) in spins.genrows_mut().into_iter()
+        .zip(configs.iter().flat_map(|c| itertools::repeat_n(c, replicas)))
+    {
+        for (i, &s) in config.iter().enumerate(){
+            assert!(s == 1 || s == -1, "ising_to_spins: spins must be +1 or -1");
+            row[i / 4][2].dat[i % 4] = s as f64;
+        }
+    }
+
+    spins
+}
+
+/// Spin array of `rows` replicas of n spins aligned to +x, the ground state of the transverse
+/// field at the start of a forward anneal
+pub fn transverse_spins(rows: usize, n: usize) -> Array2<Vector3d4xf64>{
+    let n_ch = num_chunks(n);
+    let mut spins = Array2::from_elem((rows, n_ch), Vector3d4xf64::zero());
+    for mut row in spins.genrows_mut(){
+        for i in 0..n{
+            row[i / 4][0].dat[i % 4] = 1.0;
+        }
+    }
+
+    spins
+}
+
+/// Project each row of a spin array to the Ising configuration sign(m_z) of `readout::z_sign`
+pub fn spins_to_ising(spins: &Array2<Vector3d4xf64>, n: usize) -> Vec<Vec<i8>>{
+    spins.genrows().into_iter().map(|row|{
+        assert!(row.len() * 4 >= n, "spins_to_ising: not enough chunks for {} spins", n);
+        (0..n).map(|i| z_sign(row[i / 4][2].dat[i % 4])).collect()
+    }).collect()
+}
+
+/// Final state of an annealing protocol
+pub struct AnnealResult{
+    pub spins: Array2<Vector3d4xf64>,
+    pub configs: Vec<Vec<i8>>
+}
+
+/// Fixed step annealing protocol
+pub struct AnnealProtocol<I: SpinIntegrator = MagnusScheme>{
+    pub delta_t: f64,
+    pub eta: f64,
+    pub b: f64,
+    pub coupling: BathCoupling,
+    pub scheme: I
+}
+
+impl AnnealProtocol{
+    pub fn new(delta_t: f64, eta: f64, b: f64) -> Self{
+        Self::with_scheme(MagnusScheme::default(), delta_t, eta, b)
+    }
+}
+
+impl<I: SpinIntegrator> AnnealProtocol<I>{
+    pub fn with_scheme(scheme: I, delta_t: f64, eta: f64, b: f64) -> Self{
+        assert!(delta_t > 0.0, "AnnealProtocol: the time step must be positive");
+        Self{delta_t, eta, b, coupling: BathCoupling::default(), scheme}
+    }
+
+    /// Select the axes through which the spins couple to the bath
+    pub fn with_coupling(mut self, coupling: BathCoupling) -> Self{
+        self.coupling = coupling;
+        self
+    }
+
+    /// Propagate `spins` along the annealing path of `ham`. The path duration is divided into
+    /// steps no longer than `delta_t`.
+    pub fn run<Fr>(
+        &self,
+        ham: &AnnealingHamiltonian,
+        spins: &mut Array2<Vector3d4xf64>,
+        seed: u64,
+        rand_xi_f: Fr
+    ) where Fr: Fn(&mut PhiloxRng) -> Vector3d4xf64 + Send + Sync
+    {
+        assert_eq!(spins.shape()[1], ham.problem().num_chunks(), "AnnealProtocol: mismatching number of chunks");
+        let t0 = ham.path().start_time();
+        let n_steps = (ham.annealing_time() / self.delta_t).ceil().max(1.0) as usize;
+        assert!(n_steps <= u32::MAX as usize, "AnnealProtocol: too many steps for the step counter");
+        let dt = ham.annealing_time() / n_steps as f64;
+        let haml_fn = ham.haml_fn();
+        let mut spins_tf = spins.clone();
+        let dephasing = DephasingBath::new(self.eta, self.b);
+        for k in 0..n_steps{
+            let t = t0 + k as f64 * dt;
+            let step = k as u32;
+            match self.coupling{
+                BathCoupling::Isotropic => spin_langevin_step_counter(
+                    spins, &mut spins_tf, t, dt, self.eta, self.b, &haml_fn, seed, step, &rand_xi_f, &self.scheme),
+                BathCoupling::Dephasing => spin_langevin_step_bath(
+                    spins, &mut spins_tf, t, dt, &dephasing, &haml_fn, seed, step, &rand_xi_f, &self.scheme)
+            };
+            std::mem::swap(spins, &mut spins_tf);
+        }
+    }
+
+    /// Run the annealing path starting from the classical configurations `initial`, each loaded on
+    /// `replicas` rows, and read out the final configurations. This is the reverse annealing
+    /// protocol when the path starts at s = 1, e.g. with `AnnealPath::reverse`.
+    pub fn run_from_configs<Fr>(
+        &self,
+        ham: &AnnealingHamiltonian,
+        initial: &[Vec<i8>],
+        replicas: usize,
+        seed: u64,
+        rand_xi_f: Fr
+    ) -> AnnealResult
+        where Fr: Fn(&mut PhiloxRng) -> Vector3d4xf64 + Send + Sync
+    {
+        assert!(initial.iter().all(|c| c.len() == ham.num_spins()), "AnnealProtocol: mismatching number of spins");
+        let mut spins = ising_to_spins(initial, replicas);
+        self.run(ham, &mut spins, seed, rand_xi_f);
+        let configs = spins_to_ising(&spins, ham.num_spins());
+
+        AnnealResult{spins, configs}
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use num_traits::Zero;
+
+    use super::{ising_to_spins, spins_to_ising, transverse_spins, AnnealProtocol};
+    use crate::hamiltonian::{AnnealPath, AnnealingHamiltonian, AnnealingSchedule, SparseCouplingHamiltonian};
+
+    #[test]
+    fn test_reverse_anneal(){
+        let configs = vec![vec![1, -1, 1, 1, -1], vec![-1, -1, -1, -1, -1]];
+        let spins = ising_to_spins(&configs, 3);
+        assert_eq!(spins.shape(), &[6, 2]);
+        assert_eq!(spins_to_ising(&spins, 5)[4], configs[1]);
+        assert_eq!(ising_to_spins(&[vec![]], 2).shape(), &[2, 0]);
+        assert_eq!(transverse_spins(3, 0).shape(), &[3, 0]);
+
+        // Ferromagnetic chain with a bias towards +1
+        let problem = SparseCouplingHamiltonian::ising(&[-0.2; 5],
+                                                       &[(0, 1, -1.0), (1, 2, -1.0), (2, 3, -1.0), (3, 4, -1.0)]);
+        let protocol = AnnealProtocol::new(0.01, 0.5, 0.0);
+
+        // Without returning into the transverse field the classical states are stationary
+        let ham = AnnealingHamiltonian::with_path(AnnealingSchedule::linear(2.0, 2.0), &problem,
+                                                  AnnealPath::reverse(1.0, 1.0, 1.0));
+        let res = protocol.run_from_configs(&ham, &configs, 3, 11, |_r| Zero::zero());
+        assert_eq!(res.configs[0], configs[0]);
+        assert_eq!(res.configs[5], configs[1]);
+
+        // With dissipation, a reverse anneal relaxes the domain walls of the first configuration to
+        // the ground state, while the all-down state is a semiclassical local minimum
+        let ham = AnnealingHamiltonian::with_path(AnnealingSchedule::linear(2.0, 2.0), &problem,
+                                                  AnnealPath::reverse(0.4, 5.0, 20.0));
+        let res = protocol.run_from_configs(&ham, &configs, 1, 11, |_r| Zero::zero());
+        assert_eq!(res.configs[0], vec![1; 5]);
+        assert_eq!(res.configs[1], configs[1]);
+    }
+}
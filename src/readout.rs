@@ -0,0 +1,123 @@
+//! Readout of Ising configurations from the final spins of an anneal
+//!
+//! Each row of a spin array is an independent replica, with spin i in lane i % 4 of chunk i / 4.
+//! A spin is projected to sign(m_z), unless |m_z| is below the readout threshold, in which case
+//! the spin is undecided and read out as a uniformly random sign.
+
+use rand::Rng;
+use rayon::prelude::*;
+use ndarray::{Array2, ArrayView1};
+use simd_phys::r3::Vector3d4xf64;
+
+use crate::ising::IsingProblem;
+
+/// The Ising spin sign(m_z) of a spin, with m_z = 0 read as +1
+pub fn z_sign(mz: f64) -> i8{
+    if mz < 0.0 { -1 } else { 1 }
+}
+
+/// Project a row of spins to an Ising configuration of n spins
+pub fn project_row<R: Rng>(row: ArrayView1<Vector3d4xf64>, n: usize, threshold: f64, rng: &mut R) -> Vec<i8>{
+    assert!(row.len() * 4 >= n, "project_row: not enough chunks for {} spins", n);
+    (0..n).map(|i|{
+        let mz = row[i / 4][2].dat[i % 4];
+        if mz.abs() < threshold{
+            if rng.gen::<bool>() { 1 } else { -1 }
+        } else {
+            z_sign(mz)
+        }
+    }).collect()
+}
+
+/// Configurations and energies of all replicas
+#[derive(Clone, Debug)]
+pub struct Readout{
+    pub configs: Vec<Vec<i8>>,
+    pub energies: Vec<f64>
+}
+
+impl Readout{
+    /// Project every row of `spins` and evaluate the energies against `problem`
+    pub fn new<R: Rng>(spins: &Array2<Vector3d4xf64>, problem: &IsingProblem, threshold: f64, rng: &mut R) -> Self{
+        let n = problem.num_spins();
+        let configs : Vec<Vec<i8>> = spins.genrows().into_iter()
+            .map(|row| project_row(row, n, threshold, rng))
+            .collect();
+        let energies = configs.par_iter().map(|s| problem.energy(s)).collect();
+
+        Self{configs, energies}
+    }
+
+    pub fn num_replicas(&self) -> usize{
+        self.configs.len()
+    }
+
+    /// Lowest energy found and the index of the first replica that found it
+    pub fn min_energy(&self) -> (f64, usize){
+        self.energies.iter().cloned().enumerate()
+            .fold((f64::INFINITY, 0), |(e0, k0), (k, e)| if e < e0 { (e, k) } else { (e0, k0) })
+    }
+
+    pub fn mean_energy(&self) -> f64{
+        self.energies.iter().sum::<f64>() / self.energies.len() as f64
+    }
+
+    /// Fraction of replicas with energy within `tol` of the ground state energy `e_ground`
+    pub fn ground_state_probability(&self, e_ground: f64, tol: f64) -> f64{
+        let hits = self.energies.iter().filter(|&&e| (e - e_ground).abs() <= tol).count();
+        hits as f64 / self.energies.len() as f64
+    }
+
+    /// Distinct energy levels in increasing order with their replica counts.
+    /// Energies within `tol` of the lowest energy of a level are merged into it.
+    pub fn energy_histogram(&self, tol: f64) -> Vec<(f64, usize)>{
+        let mut e = self.energies.clone();
+        e.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut hist : Vec<(f64, usize)> = Vec::new();
+        for x in e{
+            match hist.last_mut(){
+                Some((level, count)) if x - *level <= tol => *count += 1,
+                _ => hist.push((x, 1))
+            }
+        }
+
+        hist
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+
+    use super::Readout;
+    use crate::ising::IsingProblem;
+    use crate::protocol::ising_to_spins;
+
+    #[test]
+    fn test_readout_statistics(){
+        let mut rng = Xoshiro256Plus::seed_from_u64(3);
+        let problem = IsingProblem::new(vec![0.0, 0.0, 0.0, 0.0, 0.5],
+                                        vec![(0, 1, -1.0), (1, 2, -1.0), (2, 3, -1.0), (3, 4, -1.0)]);
+        let configs = vec![vec![-1; 5], vec![1; 5], vec![-1, -1, -1, 1, 1], vec![-1; 5]];
+        let mut spins = ising_to_spins(&configs, 1);
+
+        let readout = Readout::new(&spins, &problem, 0.1, &mut rng);
+        assert_eq!(readout.configs, configs);
+        assert_eq!(readout.energies, vec![-4.5, -3.5, -1.5, -4.5]);
+        assert_eq!(readout.min_energy(), (-4.5, 0));
+        assert_eq!(readout.ground_state_probability(-4.5, 1.0e-9), 0.5);
+        assert_eq!(readout.energy_histogram(1.0e-9), vec![(-4.5, 2), (-3.5, 1), (-1.5, 1)]);
+
+        // Undecided spins are read out with random signs
+        spins[(1, 1)][2].dat[0] = 0.05;
+        let mut ups = 0;
+        for _ in 0..1000{
+            let readout = Readout::new(&spins, &problem, 0.1, &mut rng);
+            if readout.configs[1][4] == 1{
+                ups += 1;
+            }
+        }
+        assert!(ups > 400 && ups < 600);
+    }
+}
@@ -0,0 +1,236 @@
+//! Spin-transfer torques as effective field contributions
+//!
+//! A torque \tau added to the precession  dm/dt = -m \cross h  is expressed by a field h_\tau with
+//! -m \cross h_\tau = \tau. For unit spins,
+//!     Slonczewski damping-like  -a_J m \cross (m \cross p)      h = a_J m \cross p
+//!     Slonczewski field-like    -b_J m \cross p                 h = b_J p
+//!     Zhang-Li adiabatic        -(u \cdot \nabla) m             h = -m \cross (u \cdot \nabla) m
+//!     Zhang-Li non-adiabatic    \beta m \cross (u \cdot \nabla) m     h = -\beta (u \cdot \nabla) m
+//! with the spin polarization p, and the spin drift velocity u of the current.
+//! The torque fields depend on m, so they are evaluated together with the Hamiltonian fields at
+//! every stage of a step. They are added before the dissipative term of `h_update_row`, i.e. the
+//! torques enter the Gilbert form of the equation, as in the usual LLG formulation of spin-transfer
+//! torque. The coefficients are in the field units of the Spin-Langevin equation, and
+//! `slonczewski_field` gives a_J in tesla for `llg::LlgUnits::field_from_tesla`.
+//!
+//! `with_torque` composes any Hamiltonian closure with a `TorqueTerm`, and pairs of terms are
+//! themselves terms.
+
+use nalgebra::Vector3;
+use ndarray::{ArrayView1, ArrayViewMut1};
+use simd_phys::r3::Vector3d4xf64;
+use simd_phys::vf64::Aligned4xf64;
+
+use crate::bath::Coefficient;
+
+/// Reduced Planck constant (J s)
+pub const HBAR: f64 = 1.054_571_817e-34;
+/// Elementary charge (C)
+pub const E_CHARGE: f64 = 1.602_176_634e-19;
+
+/// The Slonczewski damping-like coefficient  a_J = \hbar P J / (2 e M_s d)  in tesla, for the
+/// current density J (A / m^2), spin polarization P, saturation magnetization M_s (A / m) and
+/// free layer thickness d (m)
+pub fn slonczewski_field(current_density: f64, polarization: f64, ms: f64, thickness: f64) -> f64{
+    HBAR * polarization * current_density / (2.0 * E_CHARGE * ms * thickness)
+}
+
+/// A torque on the spins, expressed as a field added to the local fields of a row
+pub trait TorqueTerm : Sync{
+    fn add_torque_field(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>);
+}
+
+impl<A: TorqueTerm, B: TorqueTerm> TorqueTerm for (A, B){
+    fn add_torque_field(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        self.0.add_torque_field(t, m, h);
+        self.1.add_torque_field(t, m, h);
+    }
+}
+
+/// The Hamiltonian closure `haml_fn` with the torque fields of `torque` added
+pub fn with_torque<Fh, T>(haml_fn: Fh, torque: T)
+    -> impl Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync
+    where Fh: Fn(f64, &ArrayView1<Vector3d4xf64>, &mut ArrayViewMut1<Vector3d4xf64>) + Sync,
+          T: TorqueTerm
+{
+    move |t, m, h|{
+        haml_fn(t, m, h);
+        torque.add_torque_field(t, m, h);
+    }
+}
+
+#[inline]
+fn splat(v: &Vector3<f64>) -> Vector3d4xf64{
+    Vector3d4xf64::new(Aligned4xf64::from(v[0]), Aligned4xf64::from(v[1]), Aligned4xf64::from(v[2]))
+}
+
+/// Slonczewski damping-like and field-like torques with a fixed polarization p, applied to all
+/// spins. The coefficients may depend on time, e.g. through a current pulse.
+#[derive(Copy, Clone, Debug)]
+pub struct SlonczewskiTorque<A=f64, B=f64>{
+    pub p: Vector3<f64>,
+    pub a_j: A,
+    pub b_j: B
+}
+
+impl<A: Coefficient, B: Coefficient> SlonczewskiTorque<A, B>{
+    /// Torques with the polarization direction `p` (normalized), the damping-like coefficient
+    /// a_J and the field-like coefficient b_J
+    pub fn new(p: Vector3<f64>, a_j: A, b_j: B) -> Self{
+        let norm = p.norm();
+        assert!(norm > 0.0, "SlonczewskiTorque: zero polarization vector");
+        Self{p: p / norm, a_j, b_j}
+    }
+}
+
+impl<A: Coefficient, B: Coefficient> TorqueTerm for SlonczewskiTorque<A, B>{
+    fn add_torque_field(&self, t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        let p = splat(&self.p);
+        let a_j = Aligned4xf64::from(self.a_j.at(t));
+        let h_fl = p * Aligned4xf64::from(self.b_j.at(t));
+        for (hc, mc) in h.iter_mut().zip(m.iter()){
+            *hc += mc.cross(&p) * a_j + h_fl;
+        }
+    }
+}
+
+/// Zhang-Li adiabatic and non-adiabatic torques on a regular lattice, with the spatial derivative
+/// (u \cdot \nabla) m evaluated by finite differences
+#[derive(Clone, Debug)]
+pub struct ZhangLiTorque{
+    /// Finite difference stencil of (u \cdot \nabla) for each spin
+    stencil: Vec<Vec<(usize, f64)>>,
+    pub beta: f64
+}
+
+impl ZhangLiTorque{
+    /// Torques with the non-adiabaticity \beta and the derivative stencils (j, w_{ij}) of each spin i,
+    /// such that  (u \cdot \nabla) m_i = \sum_j w_{ij} m_j
+    pub fn new(stencil: Vec<Vec<(usize, f64)>>, beta: f64) -> Self{
+        let n = stencil.len();
+        assert!(stencil.iter().flatten().all(|&(j, _)| j < n), "ZhangLiTorque: stencil index out of range");
+        Self{stencil, beta}
+    }
+
+    /// Torques on the lattice of `dims` = (n_x, n_y, n_z) sites with the spacing `a`, spin index
+    /// i = x + n_x (y + n_y z), for the drift velocity `u`. Derivatives are central differences,
+    /// one-sided at open boundaries.
+    pub fn lattice(dims: [usize; 3], periodic: bool, a: f64, u: Vector3<f64>, beta: f64) -> Self{
+        assert!(dims.iter().all(|&d| d > 0) && a > 0.0, "ZhangLiTorque: invalid lattice");
+        let n = dims[0] * dims[1] * dims[2];
+        let index = |c: [usize; 3]| c[0] + dims[0] * (c[1] + dims[1] * c[2]);
+        let stencil = (0..n).map(|i|{
+            let c = [i % dims[0], (i / dims[0]) % dims[1], i / (dims[0] * dims[1])];
+            let mut s = Vec::new();
+            for d in 0..3{
+                if u[d] == 0.0 || dims[d] == 1{
+                    continue;
+                }
+                let w = u[d] / a;
+                let step = |k: usize, fwd: bool| -> Option<usize>{
+                    match (fwd, k){
+                        (true, k) if k + 1 < dims[d] => Some(k + 1),
+                        (true, _) => if periodic { Some(0) } else { None },
+                        (false, 0) => if periodic { Some(dims[d] - 1) } else { None },
+                        (false, k) => Some(k - 1)
+                    }
+                };
+                let at = |k: usize|{ let mut c2 = c; c2[d] = k; index(c2) };
+                match (step(c[d], true), step(c[d], false)){
+                    (Some(f), Some(b)) => { s.push((at(f), w / 2.0)); s.push((at(b), -w / 2.0)); },
+                    (Some(f), None) => { s.push((at(f), w)); s.push((i, -w)); },
+                    (None, Some(b)) => { s.push((i, w)); s.push((at(b), -w)); },
+                    (None, None) => {}
+                }
+            }
+            s
+        }).collect();
+
+        Self::new(stencil, beta)
+    }
+
+    pub fn num_spins(&self) -> usize{
+        self.stencil.len()
+    }
+}
+
+impl TorqueTerm for ZhangLiTorque{
+    fn add_torque_field(&self, _t: f64, m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>){
+        assert!(m.len() * 4 >= self.num_spins(), "ZhangLiTorque: not enough chunks for the lattice");
+        let beta = Aligned4xf64::from(self.beta);
+        for (c, (hc, mc)) in h.iter_mut().zip(m.iter()).enumerate(){
+            // Gather (u \cdot \nabla) m for the lanes of the chunk
+            let mut v = Vector3d4xf64::zeros();
+            for l in 0..4{
+                if let Some(st) = self.stencil.get(4 * c + l){
+                    for &(j, w) in st.iter(){
+                        let mj = &m[j / 4];
+                        for a in 0..3{
+                            v[a].dat[l] += w * mj[a].dat[j % 4];
+                        }
+                    }
+                }
+            }
+            *hc -= mc.cross(&v) + v * beta;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Mutex;
+
+    use nalgebra::Vector3;
+    use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1};
+    use num_traits::Zero;
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256Plus;
+    use simd_phys::r3::Vector3d4xf64;
+    use simd_phys::vf64::Aligned4xf64;
+
+    use super::{with_torque, SlonczewskiTorque, TorqueTerm, ZhangLiTorque};
+    use crate::{spin_langevin_step_scheme, MagnusScheme};
+
+    #[test]
+    fn test_spin_torques(){
+        let zero_fn = |_t: f64, _m: &ArrayView1<Vector3d4xf64>, h: &mut ArrayViewMut1<Vector3d4xf64>|{
+            h.fill(Zero::zero());
+        };
+        // Damping-like torque alone aligns the spins with p:  \tan(\theta / 2) = \exp(-a_J t)
+        let a_j = 0.5;
+        let haml_fn = with_torque(zero_fn, SlonczewskiTorque::new(Vector3::z(), a_j, 0.0));
+        let rng_arr : Vec<Mutex<Xoshiro256Plus>> = (0..rayon::current_num_threads())
+            .map(|k| Mutex::new(Xoshiro256Plus::seed_from_u64(k as u64))).collect();
+        let rand_xi_f = |_rng: &mut Xoshiro256Plus| Vector3d4xf64::zeros();
+        let mut m : Array2<Vector3d4xf64> = Array2::from_elem((1, 1), Zero::zero());
+        m[(0, 0)][0] = Aligned4xf64::from(1.0);
+        let mut mf = m.clone();
+        for k in 0..100{
+            spin_langevin_step_scheme(&m, &mut mf, k as f64 * 0.02, 0.02, 0.0, 0.0, &haml_fn,
+                                      &rng_arr, rand_xi_f, &MagnusScheme::Magnus2);
+            std::mem::swap(&mut m, &mut mf);
+        }
+        let theta = 2.0 * (-a_j * 2.0f64).exp().atan();
+        assert!((m[(0, 0)][2].dat[0] - theta.cos()).abs() < 1.0e-4);
+
+        // Zhang-Li fields of a spiral m(x) = (\cos kx, \sin kx, 0) on a periodic chain
+        let (n, k, u, beta) = (16, 2.0 * std::f64::consts::PI / 16.0, 0.3, 0.1);
+        let zl = ZhangLiTorque::lattice([n, 1, 1], true, 1.0, Vector3::new(u, 0.0, 0.0), beta);
+        let mut m = Array1::from_elem(n / 4, Vector3d4xf64::zeros());
+        for i in 0..n{
+            m[i / 4][0].dat[i % 4] = (k * i as f64).cos();
+            m[i / 4][1].dat[i % 4] = (k * i as f64).sin();
+        }
+        let mut h = Array1::from_elem(n / 4, Vector3d4xf64::zeros());
+        zl.add_torque_field(0.0, &m.view(), &mut h.view_mut());
+        // (u \cdot \nabla) m = u k_eff (-\sin kx, \cos kx, 0) with the central difference k_eff = \sin k
+        let ke = u * k.sin();
+        for i in 0..n{
+            let x = k * i as f64;
+            let hi = |a: usize| h[i / 4][a].dat[i % 4];
+            assert!((hi(0) - beta * ke * x.sin()).abs() < 1.0e-12);
+            assert!((hi(1) + beta * ke * x.cos()).abs() < 1.0e-12);
+            assert!((hi(2) + ke).abs() < 1.0e-12);
+        }
+    }
+}